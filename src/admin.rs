@@ -0,0 +1,99 @@
+use anyhow::Result;
+use hyper::body::Incoming;
+use hyper::header::AUTHORIZATION;
+use hyper::{Method, Request, Response, StatusCode};
+use log::{debug, info, warn};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::auth::authenticate;
+use crate::config::ConfigStore;
+use crate::http::{empty, json, serve, text, unauthorized, Body};
+use crate::session::{SessionId, SessionRegistry};
+
+/// REST API for operators, served on its own listener:
+///
+/// - `GET /sessions` lists the active sessions.
+/// - `GET /sessions/{id}` shows one session with its children's recent stderr.
+/// - `DELETE /sessions/{id}` force-closes a session.
+/// - `POST /sessions/{id}/restart` replaces the session's child process.
+/// - `GET /config` returns the effective configuration with secrets redacted.
+struct Admin {
+    config: Arc<ConfigStore>,
+    registry: Arc<SessionRegistry>,
+}
+
+/// Runs the admin API listener until the listener fails.
+pub async fn run_admin_server(
+    listener: TcpListener,
+    config: Arc<ConfigStore>,
+    registry: Arc<SessionRegistry>,
+) -> Result<()> {
+    let admin = Arc::new(Admin { config, registry });
+    serve(listener, move |request, addr| {
+        let admin = Arc::clone(&admin);
+        async move { admin.handle(request, addr).await }
+    })
+    .await
+}
+
+impl Admin {
+    async fn handle(&self, request: Request<Incoming>, addr: SocketAddr) -> Response<Body> {
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        debug!("Admin {} {} from {}", method, path, addr);
+
+        let config = self.config.current();
+        let Some(admin) = &config.admin else {
+            return text(StatusCode::NOT_FOUND, "Not found");
+        };
+        // Only the Authorization header is accepted; tokens in URLs end up in logs.
+        let header = request.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok());
+        if let Err(e) = authenticate(Some(&admin.auth), header, None) {
+            warn!("Admin request from {} rejected: {}", addr, e);
+            return unauthorized(&e);
+        }
+
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        match (&method, segments.as_slice()) {
+            (&Method::GET, ["sessions"]) => json(StatusCode::OK, &json!(self.registry.list().await)),
+            (&Method::GET, ["sessions", id]) => match parse_id(id) {
+                Some(id) => match self.registry.get(id).await {
+                    Some(session) => json(StatusCode::OK, &json!(session)),
+                    None => session_not_found(),
+                },
+                None => session_not_found(),
+            },
+            (&Method::DELETE, ["sessions", id]) => match parse_id(id) {
+                Some(id) if self.registry.close(id).await => {
+                    info!("Session {} closed through the admin API by {}", id, addr);
+                    empty(StatusCode::NO_CONTENT)
+                }
+                _ => session_not_found(),
+            },
+            (&Method::POST, ["sessions", id, "restart"]) => match parse_id(id) {
+                Some(id) => match self.registry.restart(id).await {
+                    Some(true) => json(StatusCode::ACCEPTED, &json!({ "restarting": true })),
+                    Some(false) => text(StatusCode::CONFLICT, "Child process is no longer running"),
+                    None => session_not_found(),
+                },
+                None => session_not_found(),
+            },
+            (&Method::GET, ["config"]) => json(StatusCode::OK, &json!(config.redacted())),
+            (_, ["sessions"] | ["sessions", _] | ["sessions", _, "restart"] | ["config"]) => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "Not found"),
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Option<SessionId> {
+    id.parse().ok()
+}
+
+fn session_not_found() -> Response<Body> {
+    text(StatusCode::NOT_FOUND, "Session not found")
+}
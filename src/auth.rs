@@ -0,0 +1,220 @@
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use crate::config::model::AuthConfig;
+use crate::routing::query_param;
+
+/// Query parameter clients can use instead of the `Authorization` header.
+const TOKEN_PARAM: &str = "token";
+
+/// What an authenticated client is allowed to connect to.
+#[derive(Debug, Clone)]
+pub struct Grant {
+    name: String,
+    servers: Option<Vec<String>>,
+}
+
+/// Reason a client was refused.
+#[derive(Debug)]
+pub enum AuthError {
+    /// No credential was presented.
+    Missing,
+    /// The credential does not match any configured token or API key.
+    Invalid,
+    /// The credential is valid but not for the requested server.
+    Forbidden { name: String, server: String },
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Missing => write!(f, "Authentication required"),
+            AuthError::Invalid => write!(f, "Invalid credentials"),
+            AuthError::Forbidden { name, server } => {
+                write!(f, "Credential '{}' is not allowed to access server '{}'", name, server)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl AuthError {
+    /// True if the client should be asked to authenticate (401) rather than refused (403).
+    pub fn is_challenge(&self) -> bool {
+        !matches!(self, AuthError::Forbidden { .. })
+    }
+}
+
+impl Grant {
+    /// Grant used when authentication is disabled.
+    fn anonymous() -> Self {
+        Self { name: "anonymous".to_string(), servers: None }
+    }
+
+    /// Checks that this grant covers `server`.
+    pub fn authorize(&self, server: &str) -> Result<(), AuthError> {
+        match &self.servers {
+            Some(servers) if !servers.iter().any(|allowed| allowed == server) => {
+                warn!("Credential '{}' denied access to server '{}'", self.name, server);
+                Err(AuthError::Forbidden { name: self.name.clone(), server: server.to_string() })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Authenticates a client from its `Authorization` header value or `token` query parameter.
+/// Every client is let through when `auth` is `None`.
+pub fn authenticate(
+    auth: Option<&AuthConfig>,
+    authorization: Option<&str>,
+    query: Option<&str>,
+) -> Result<Grant, AuthError> {
+    let Some(auth) = auth else {
+        return Ok(Grant::anonymous());
+    };
+
+    let credential = authorization
+        .and_then(bearer_token)
+        .map(str::to_string)
+        .or_else(|| query.and_then(|query| query_param(query, TOKEN_PARAM)))
+        .ok_or(AuthError::Missing)?;
+    let digest = sha256_hex(&credential);
+
+    // Every configured credential is compared, digest against digest, so the
+    // time taken reveals neither the credential nor which entry it matched.
+    let tokens = auth
+        .tokens
+        .iter()
+        .map(|token| (sha256_hex(&token.token), token.name.as_deref(), "token", &token.servers));
+    let keys = auth
+        .api_keys
+        .iter()
+        .map(|key| (key.sha256.to_ascii_lowercase(), key.name.as_deref(), "api key", &key.servers));
+    let mut matched = None;
+    for (expected, name, kind, servers) in tokens.chain(keys) {
+        let equal = constant_time_eq(&digest, &expected);
+        if equal && matched.is_none() {
+            matched = Some((name, kind, servers));
+        }
+    }
+
+    match matched {
+        Some((name, kind, servers)) => Ok(grant(name, kind, servers)),
+        None => {
+            warn!("Rejected invalid credential");
+            Err(AuthError::Invalid)
+        }
+    }
+}
+
+fn grant(name: Option<&str>, kind: &str, servers: &Option<Vec<String>>) -> Grant {
+    let name = name.map_or_else(|| format!("unnamed {}", kind), str::to_string);
+    debug!("Authenticated client as '{}'", name);
+    Grant { name, servers: servers.clone() }
+}
+
+/// Extracts the token from a `Bearer` authorization header value.
+fn bearer_token(value: &str) -> Option<&str> {
+    let (scheme, token) = value.trim().split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+fn sha256_hex(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn auth() -> AuthConfig {
+        serde_json::from_value(json!({
+            "tokens": [{ "name": "ci", "token": "ci-token", "servers": ["github"] }],
+            "api_keys": [{ "sha256": sha256_hex("api key").to_ascii_uppercase() }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn disabled_auth_lets_everyone_in() {
+        let grant = authenticate(None, None, None).unwrap();
+        assert!(grant.authorize("anything").is_ok());
+    }
+
+    #[test]
+    fn bearer_header_and_query_token() {
+        let auth = auth();
+        assert!(authenticate(Some(&auth), Some("Bearer ci-token"), None).is_ok());
+        assert!(authenticate(Some(&auth), Some("  bearer   ci-token "), None).is_ok());
+        assert!(authenticate(Some(&auth), None, Some("server=github&token=ci-token")).is_ok());
+        assert!(authenticate(Some(&auth), None, Some("token=api+key")).is_ok());
+        assert!(authenticate(Some(&auth), None, Some("token=api%20key")).is_ok());
+    }
+
+    #[test]
+    fn header_takes_precedence_over_query() {
+        let auth = auth();
+        let error = authenticate(Some(&auth), Some("Bearer wrong"), Some("token=ci-token")).unwrap_err();
+        assert!(matches!(error, AuthError::Invalid));
+        // A header with another scheme is ignored.
+        assert!(authenticate(Some(&auth), Some("Basic Y2k6dG9rZW4="), Some("token=ci-token")).is_ok());
+    }
+
+    #[test]
+    fn missing_and_invalid_credentials_are_challenged() {
+        let auth = auth();
+        let missing = authenticate(Some(&auth), None, Some("token=")).unwrap_err();
+        assert!(matches!(missing, AuthError::Missing));
+        assert!(missing.is_challenge());
+        assert!(matches!(authenticate(Some(&auth), Some("Bearer"), None), Err(AuthError::Missing)));
+        assert!(matches!(authenticate(Some(&auth), Some("Bearer ci-token2"), None), Err(AuthError::Invalid)));
+        // The stored digest itself is not a credential.
+        let digest = sha256_hex("api key");
+        let header = format!("Bearer {}", digest);
+        assert!(matches!(authenticate(Some(&auth), Some(&header), None), Err(AuthError::Invalid)));
+    }
+
+    #[test]
+    fn grants_are_limited_to_their_servers() {
+        let auth = auth();
+        let grant = authenticate(Some(&auth), Some("Bearer ci-token"), None).unwrap();
+        assert!(grant.authorize("github").is_ok());
+        let error = grant.authorize("filesystem").unwrap_err();
+        assert!(!error.is_challenge());
+        assert_eq!(error.to_string(), "Credential 'ci' is not allowed to access server 'filesystem'");
+
+        let grant = authenticate(Some(&auth), Some("Bearer api key"), None).unwrap();
+        assert!(grant.authorize("filesystem").is_ok());
+    }
+
+    #[test]
+    fn first_matching_entry_wins() {
+        let auth: AuthConfig = serde_json::from_value(json!({
+            "tokens": [{ "name": "first", "token": "shared", "servers": ["github"] }],
+            "api_keys": [{ "name": "second", "sha256": sha256_hex("shared") }]
+        }))
+        .unwrap();
+        let grant = authenticate(Some(&auth), Some("Bearer shared"), None).unwrap();
+        assert_eq!(grant.name, "first");
+        assert!(grant.authorize("filesystem").is_err());
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_strings() {
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+        assert!(constant_time_eq("", ""));
+    }
+}
@@ -0,0 +1,265 @@
+//! Replies to client batches on transports where the child's output is a
+//! single stream (WebSocket and the legacy SSE transport), and in the
+//! routers that split a batch into single requests (shared servers and the
+//! gateway).
+
+use log::debug;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::jsonrpc;
+
+/// Batches whose reply is still waiting for the child.
+///
+/// A JSON-RPC batch is answered with one array. When the runner answers some
+/// members itself (invalid messages, refused tool calls), those errors are
+/// held here until the child has answered the forwarded requests of the same
+/// batch, and are then sent together with the child's responses.
+#[derive(Debug, Default)]
+pub struct BatchReplies {
+    pending: Mutex<Vec<Batch>>,
+}
+
+#[derive(Debug)]
+struct Batch {
+    /// Serialized ids of the forwarded requests that are not answered yet.
+    waiting: HashSet<String>,
+    responses: Vec<Value>,
+}
+
+impl BatchReplies {
+    /// Takes the runner's own replies to a frame before `forward` is sent to
+    /// the child. Returns what to send to the client right away, if anything;
+    /// replies to a batch that still has requests for the child are held.
+    pub fn reply(&self, forward: Option<&Value>, errors: Vec<Value>, batch: bool) -> Option<Value> {
+        let waiting: HashSet<String> = forward
+            .filter(|_| batch && !errors.is_empty())
+            .map(jsonrpc::request_ids)
+            .unwrap_or_default()
+            .iter()
+            .map(Value::to_string)
+            .collect();
+        if waiting.is_empty() {
+            return jsonrpc::reply(errors, batch);
+        }
+
+        debug!("Holding {} error(s) until {} batch request(s) are answered", errors.len(), waiting.len());
+        self.pending.lock().unwrap().push(Batch { waiting, responses: errors });
+        None
+    }
+
+    /// Passes a line written by the child. Responses belonging to a held
+    /// batch are kept back; returns the lines to send to the client, with a
+    /// completed batch as one array.
+    pub fn complete(&self, line: String) -> Vec<String> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return vec![line];
+        }
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            return vec![line];
+        };
+
+        let (messages, batch) = match message {
+            Value::Array(messages) => (messages, true),
+            message => (vec![message], false),
+        };
+        let total = messages.len();
+        let mut rest = Vec::new();
+        let mut lines = Vec::new();
+        for message in messages {
+            let key = jsonrpc::is_response(&message).then(|| message["id"].to_string());
+            let held = key.and_then(|key| pending.iter().position(|batch| batch.waiting.contains(&key)).map(|index| (index, key)));
+            let Some((index, key)) = held else {
+                rest.push(message);
+                continue;
+            };
+            let batch = &mut pending[index];
+            batch.waiting.remove(&key);
+            batch.responses.push(message);
+            if batch.waiting.is_empty() {
+                let batch = pending.remove(index);
+                lines.push(Value::Array(batch.responses).to_string());
+            }
+        }
+
+        if rest.len() == total {
+            lines.insert(0, line);
+        } else if let Some(rest) = jsonrpc::reply(rest, batch) {
+            lines.insert(0, rest.to_string());
+        }
+        lines
+    }
+}
+
+/// Client batches a router split into single requests.
+///
+/// Each request routed on behalf of a batch is expected here, and its
+/// response (or expiry) is counted against the batch. Once the whole batch
+/// has been routed and every expected response arrived, the responses are
+/// handed back as one array for the client that sent it.
+#[derive(Debug)]
+pub struct PendingBatches<C> {
+    next_id: u64,
+    open: HashMap<u64, OpenBatch<C>>,
+}
+
+#[derive(Debug)]
+struct OpenBatch<C> {
+    client: C,
+    /// Number of routed requests that are not answered yet.
+    waiting: usize,
+    /// Set once every member of the batch has been routed.
+    routed: bool,
+    responses: Vec<Value>,
+}
+
+impl<C> Default for PendingBatches<C> {
+    fn default() -> Self {
+        Self { next_id: 0, open: HashMap::new() }
+    }
+}
+
+impl<C: Copy + PartialEq> PendingBatches<C> {
+    /// Starts gathering the replies to a batch sent by `client`; returns its id.
+    pub fn open(&mut self, client: C) -> u64 {
+        self.next_id += 1;
+        self.open.insert(self.next_id, OpenBatch { client, waiting: 0, routed: false, responses: Vec::new() });
+        self.next_id
+    }
+
+    /// Counts a request of the batch whose response is still to come.
+    pub fn expect(&mut self, batch: u64) {
+        if let Some(open) = self.open.get_mut(&batch) {
+            open.waiting += 1;
+        }
+    }
+
+    /// Adds a reply the router produced itself while routing the batch.
+    pub fn push(&mut self, batch: u64, response: Value) {
+        if let Some(open) = self.open.get_mut(&batch) {
+            open.responses.push(response);
+        }
+    }
+
+    /// Settles an expected request, with its response or `None` if the
+    /// client no longer waits for one. Returns the client and the batch
+    /// reply once nothing else is outstanding.
+    pub fn answer(&mut self, batch: u64, response: Option<Value>) -> Option<(C, Value)> {
+        let open = self.open.get_mut(&batch)?;
+        open.waiting = open.waiting.saturating_sub(1);
+        open.responses.extend(response);
+        self.finish(batch)
+    }
+
+    /// Marks the batch as fully routed. Returns the client and the batch
+    /// reply if nothing is outstanding.
+    pub fn close(&mut self, batch: u64) -> Option<(C, Value)> {
+        self.open.get_mut(&batch)?.routed = true;
+        self.finish(batch)
+    }
+
+    /// Forgets the batches of a client that went away.
+    pub fn remove_client(&mut self, client: C) {
+        self.open.retain(|_, open| open.client != client);
+    }
+
+    fn finish(&mut self, batch: u64) -> Option<(C, Value)> {
+        let open = self.open.get(&batch)?;
+        if !open.routed || open.waiting > 0 {
+            return None;
+        }
+        let open = self.open.remove(&batch)?;
+        debug!("Replying to a batch with {} response(s)", open.responses.len());
+        // A batch of notifications gets no reply at all.
+        (!open.responses.is_empty()).then_some((open.client, Value::Array(open.responses)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn error(id: Value) -> Value {
+        jsonrpc::error_response(id, jsonrpc::INVALID_REQUEST, "Invalid Request")
+    }
+
+    #[test]
+    fn errors_without_forwarded_requests_are_sent_at_once() {
+        let replies = BatchReplies::default();
+        let notification = json!([{ "jsonrpc": "2.0", "method": "notifications/initialized" }]);
+        assert_eq!(replies.reply(Some(&notification), vec![error(Value::Null)], true), Some(json!([error(Value::Null)])));
+        assert_eq!(replies.reply(None, Vec::new(), true), None);
+    }
+
+    #[test]
+    fn batch_errors_wait_for_the_child() {
+        let replies = BatchReplies::default();
+        let forward = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "a" },
+            { "jsonrpc": "2.0", "id": 2, "method": "b" },
+        ]);
+        assert_eq!(replies.reply(Some(&forward), vec![error(json!(3))], true), None);
+
+        // Unrelated output passes through untouched.
+        let notification = r#"{"jsonrpc":"2.0","method":"notifications/progress"}"#.to_string();
+        assert_eq!(replies.complete(notification.clone()), vec![notification]);
+
+        let first = jsonrpc::response(json!(1), json!({}));
+        assert!(replies.complete(first.to_string()).is_empty());
+
+        let second = jsonrpc::response(json!(2), json!({}));
+        let lines = replies.complete(second.to_string());
+        assert_eq!(lines.len(), 1);
+        let reply: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(reply, json!([error(json!(3)), first, second]));
+    }
+
+    #[test]
+    fn child_batch_is_split_from_held_responses() {
+        let replies = BatchReplies::default();
+        let forward = json!([{ "jsonrpc": "2.0", "id": 1, "method": "a" }]);
+        assert_eq!(replies.reply(Some(&forward), vec![error(Value::Null)], true), None);
+
+        let other = jsonrpc::response(json!(9), json!({}));
+        let held = jsonrpc::response(json!(1), json!({}));
+        let lines = replies.complete(json!([other, held]).to_string());
+        assert_eq!(lines.len(), 2);
+        assert_eq!(serde_json::from_str::<Value>(&lines[0]).unwrap(), json!([other]));
+        assert_eq!(serde_json::from_str::<Value>(&lines[1]).unwrap(), json!([error(Value::Null), held]));
+    }
+
+    #[test]
+    fn router_batch_waits_for_every_expected_response() {
+        let mut batches = PendingBatches::default();
+        let batch = batches.open(7u64);
+        batches.expect(batch);
+        batches.expect(batch);
+        batches.push(batch, error(json!(3)));
+        assert_eq!(batches.close(batch), None);
+
+        let first = jsonrpc::response(json!(1), json!({}));
+        assert_eq!(batches.answer(batch, Some(first.clone())), None);
+        // An expired or cancelled request settles its part without a response.
+        assert_eq!(batches.answer(batch, None), Some((7, json!([error(json!(3)), first]))));
+        assert_eq!(batches.answer(batch, None), None);
+    }
+
+    #[test]
+    fn router_batch_without_requests_is_answered_on_close() {
+        let mut batches = PendingBatches::default();
+        let local = batches.open(1u64);
+        batches.push(local, error(Value::Null));
+        assert_eq!(batches.close(local), Some((1, json!([error(Value::Null)]))));
+
+        let notifications = batches.open(1);
+        assert_eq!(batches.close(notifications), None);
+
+        let abandoned = batches.open(2);
+        batches.expect(abandoned);
+        batches.remove_client(2);
+        assert_eq!(batches.close(abandoned), None);
+    }
+}
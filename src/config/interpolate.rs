@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Context, Result};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::config::model::{Config, InputConfig, Secrets};
+
+/// サーバー設定の `command`、`args`、`env` の値に含まれる参照を展開する
+///
+/// 使用できる参照:
+/// - `${NAME}`: 環境変数の値（未設定の場合はエラー）
+/// - `${NAME:-default}`: 環境変数の値（未設定または空の場合は `default`）
+/// - `${env:NAME}`: `${NAME}` と同じ（VS Code の設定ファイルとの互換用）
+/// - `${input:id}`: `inputs` に記述した入力値（`INPUT_<ID>` 環境変数、未設定の場合は `default`）
+/// - `${file:/path}`: ファイルの内容（末尾の改行を除く、相対パスは設定ファイルのディレクトリから）
+/// - `${cwd}`: ランナーの作業ディレクトリ
+///
+/// `$${` は参照ではなく `${` として扱う。環境変数とファイルから読み込んだ値は秘密情報として記録する。
+pub fn interpolate_servers(config: &mut Config, base_dir: &Path) -> Result<()> {
+    let mut secrets = Secrets::default();
+    let inputs = &config.inputs;
+    for (name, server) in &mut config.servers {
+        let mut resolve = |field: &str, value: &mut String| -> Result<()> {
+            *value = interpolate(value, base_dir, inputs, &mut secrets)
+                .with_context(|| format!("Server '{}': failed to resolve {}", name, field))?;
+            Ok(())
+        };
+        resolve("command", &mut server.command)?;
+        for (index, arg) in server.args.iter_mut().enumerate() {
+            resolve(&format!("args[{}]", index), arg)?;
+        }
+        for (key, value) in server.env.iter_mut() {
+            resolve(&format!("env '{}'", key), value)?;
+        }
+    }
+    config.secrets = secrets;
+    Ok(())
+}
+
+/// 1つの文字列に含まれる参照を全て展開する
+fn interpolate(value: &str, base_dir: &Path, inputs: &[InputConfig], secrets: &mut Secrets) -> Result<String> {
+    let mut resolved = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        resolved.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(after) = after.strip_prefix("${") {
+            resolved.push_str("${");
+            rest = after;
+        } else if let Some(body) = after.strip_prefix('{') {
+            let end = body
+                .find('}')
+                .with_context(|| format!("Unterminated reference '${{{}'", body))?;
+            resolved.push_str(&resolve(&body[..end], base_dir, inputs, secrets)?);
+            rest = &body[end + 1..];
+        } else {
+            resolved.push('$');
+            rest = after;
+        }
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+/// `${...}` の中身を値に置き換える
+fn resolve(reference: &str, base_dir: &Path, inputs: &[InputConfig], secrets: &mut Secrets) -> Result<String> {
+    if reference == "cwd" {
+        let cwd = env::current_dir().context("Failed to get the current directory")?;
+        return Ok(cwd.to_string_lossy().into_owned());
+    }
+
+    if let Some(path) = reference.strip_prefix("file:") {
+        let path = base_dir.join(path);
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read '${{{}}}' from {:?}", reference, path))?;
+        let secret = contents.trim_end_matches(['\n', '\r']).to_string();
+        secrets.push(&secret);
+        return Ok(secret);
+    }
+
+    if let Some(id) = reference.strip_prefix("input:") {
+        let input = inputs
+            .iter()
+            .find(|input| input.id == id)
+            .with_context(|| format!("Input '{}' referenced by '${{{}}}' is not declared in inputs", id, reference))?;
+        let env_var = input.env_var();
+        return match (env::var(&env_var), &input.default) {
+            (Ok(value), _) => {
+                secrets.push(&value);
+                Ok(value)
+            }
+            (Err(_), Some(default)) => Ok(default.clone()),
+            (Err(_), None) => Err(anyhow!(
+                "Environment variable '{}' for input '{}' referenced by '${{{}}}' is not set",
+                env_var,
+                id,
+                reference
+            )),
+        };
+    }
+
+    let variable = reference.strip_prefix("env:").unwrap_or(reference);
+    let (name, default) = match variable.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (variable, None),
+    };
+    if !is_variable_name(name) {
+        return Err(anyhow!("Invalid reference '${{{}}}'", reference));
+    }
+    match (env::var(name), default) {
+        (Ok(value), None) => {
+            secrets.push(&value);
+            Ok(value)
+        }
+        (Ok(value), Some(_)) if !value.is_empty() => {
+            secrets.push(&value);
+            Ok(value)
+        }
+        (_, Some(default)) => Ok(default.to_string()),
+        (Err(_), None) => Err(anyhow!("Environment variable '{}' referenced by '${{{}}}' is not set", name, reference)),
+    }
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(value: &str) -> Result<String> {
+        interpolate(value, Path::new("."), &[], &mut Secrets::default())
+    }
+
+    #[test]
+    fn plain_values_are_unchanged() {
+        assert_eq!(expand("npx -y server").unwrap(), "npx -y server");
+        assert_eq!(expand("cost: $5 or $").unwrap(), "cost: $5 or $");
+        assert_eq!(expand("").unwrap(), "");
+    }
+
+    #[test]
+    fn escaped_reference_is_kept_literally() {
+        assert_eq!(expand("$${HOME}").unwrap(), "${HOME}");
+        assert_eq!(expand("a$${b}c").unwrap(), "a${b}c");
+    }
+
+    #[test]
+    fn unterminated_reference_is_an_error() {
+        let error = expand("--token=${API_TOKEN").unwrap_err();
+        assert!(error.to_string().contains("Unterminated reference '${API_TOKEN'"), "{}", error);
+        assert!(expand("${").is_err());
+    }
+
+    #[test]
+    fn invalid_variable_names_are_errors() {
+        assert!(expand("${}").is_err());
+        assert!(expand("${1ABC}").is_err());
+        assert!(expand("${MY-VAR}").is_err());
+    }
+
+    #[test]
+    fn environment_variables_and_defaults() {
+        env::set_var("MCP_RUNNER_TEST_SET", "value");
+        env::set_var("MCP_RUNNER_TEST_EMPTY", "");
+        env::remove_var("MCP_RUNNER_TEST_UNSET");
+
+        let mut secrets = Secrets::default();
+        let resolved = interpolate(
+            "${MCP_RUNNER_TEST_SET}/${env:MCP_RUNNER_TEST_SET}/${MCP_RUNNER_TEST_EMPTY:-empty}/${MCP_RUNNER_TEST_UNSET:-a:-b}",
+            Path::new("."),
+            &[],
+            &mut secrets,
+        )
+        .unwrap();
+        assert_eq!(resolved, "value/value/empty/a:-b");
+        assert!(expand("${MCP_RUNNER_TEST_UNSET}").unwrap_err().to_string().contains("is not set"));
+        // 既定値は秘密情報として記録しない
+        assert_eq!(format!("{:?}", secrets), "Secrets(1 value(s))");
+    }
+
+    #[test]
+    fn inputs_use_environment_then_default() {
+        let inputs = [
+            InputConfig { id: "mcp-runner-test-key".to_string(), description: None, default: None },
+            InputConfig { id: "mcp-runner-test-region".to_string(), description: None, default: Some("eu".to_string()) },
+        ];
+        env::set_var("INPUT_MCP_RUNNER_TEST_KEY", "secret");
+        env::remove_var("INPUT_MCP_RUNNER_TEST_REGION");
+        let mut secrets = Secrets::default();
+        let resolved = interpolate(
+            "${input:mcp-runner-test-key}@${input:mcp-runner-test-region}",
+            Path::new("."),
+            &inputs,
+            &mut secrets,
+        )
+        .unwrap();
+        assert_eq!(resolved, "secret@eu");
+        assert!(interpolate("${input:undeclared}", Path::new("."), &inputs, &mut secrets).is_err());
+
+        env::remove_var("INPUT_MCP_RUNNER_TEST_KEY");
+        assert!(interpolate("${input:mcp-runner-test-key}", Path::new("."), &inputs, &mut secrets).is_err());
+    }
+
+    #[test]
+    fn file_references_are_relative_to_the_config() {
+        let dir = env::temp_dir().join(format!("mcp-runner-interpolate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("token"), "s3cret\r\n").unwrap();
+
+        let mut secrets = Secrets::default();
+        let resolved = interpolate("--token=${file:token}", &dir, &[], &mut secrets).unwrap();
+        assert_eq!(resolved, "--token=s3cret");
+
+        let error = interpolate("${file:missing}", &dir, &[], &mut secrets).unwrap_err();
+        assert!(error.to_string().contains("Failed to read '${file:missing}'"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,11 @@
 use anyhow::{Context, Result};
 use log::{debug, info, warn};
 use std::env;
-use std::fs::File;
-use std::io::BufReader;
+use std::fs;
 use std::path::Path;
 
-use crate::config::model::{Config, ServerConfig};
+use crate::config::interpolate::interpolate_servers;
+use crate::config::model::{AuthConfig, Config, GatewayConfig, ServerConfig};
 
 /// 設定を読み込む
 pub fn load_config(config_path: Option<&str>) -> Result<Config> {
@@ -22,21 +22,113 @@ pub fn load_config(config_path: Option<&str>) -> Result<Config> {
     // 環境変数からの設定の上書き
     merge_env_vars(&mut config);
 
+    // サーバーが1つだけの場合はそのサーバーをデフォルトとする（Claude Desktop の設定ファイルなど）
+    if config.default_server.is_none() && config.servers.len() == 1 {
+        config.default_server = config.servers.keys().next().cloned();
+    }
+
     validate_config(&config)?;
     
-    debug!("Loaded config: {:?}", config);
+    debug!("Loaded config: {:?}", config.redacted());
     Ok(config)
 }
 
-/// ファイルから設定を読み込む
+/// 設定ファイルの形式
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// 拡張子から形式を判定する（不明な場合はJSON）
+    fn from_path(path: &Path) -> Self {
+        let extension = path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("toml") => Format::Toml,
+            Some("yaml" | "yml") => Format::Yaml,
+            _ => Format::Json,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::Toml => "TOML",
+            Format::Yaml => "YAML",
+        }
+    }
+
+    /// 設定を解析する。失敗した場合は行と列を含むエラーを返す
+    fn parse(self, contents: &str) -> Result<Config> {
+        let (message, line, column) = match self {
+            Format::Json => match serde_json::from_str(contents) {
+                Ok(config) => return Ok(config),
+                Err(e) => (strip_location(&e.to_string()), e.line(), e.column()),
+            },
+            Format::Toml => match toml::from_str(contents) {
+                Ok(config) => return Ok(config),
+                Err(e) => {
+                    let offset = e.span().map_or(0, |span| span.start);
+                    let (line, column) = line_column(contents, offset);
+                    (e.message().trim_end().to_string(), line, column)
+                }
+            },
+            Format::Yaml => match serde_yaml::from_str(contents) {
+                Ok(config) => return Ok(config),
+                Err(e) => {
+                    let (line, column) = e.location().map_or((0, 0), |location| (location.line(), location.column()));
+                    (strip_location(&e.to_string()), line, column)
+                }
+            },
+        };
+        Err(anyhow::anyhow!("{} (line {}, column {})", message, line, column))
+    }
+}
+
+/// serde_json と serde_yaml のエラーメッセージから位置情報を取り除く
+///
+/// serde_yaml の構文エラーは「問題の位置」と「解析中の要素の位置」の2つを含むため、
+/// 最初の位置情報以降を取り除く。
+fn strip_location(message: &str) -> String {
+    match message.find(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message.to_string(),
+    }
+}
+
+/// バイト位置を1始まりの行と列に変換する
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |last| last.chars().count()) + 1;
+    (line, column)
+}
+
+/// ファイルから設定を読み込む（形式は拡張子で判定する）
 fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Config> {
     info!("Loading config from file: {:?}", path.as_ref());
-    let file = File::open(&path)
+    let contents = fs::read_to_string(&path)
         .with_context(|| format!("Failed to open config file: {:?}", path.as_ref()))?;
-    
-    let reader = BufReader::new(file);
-    let config: Config = serde_json::from_reader(reader)
-        .with_context(|| format!("Failed to parse JSON config from: {:?}", path.as_ref()))?;
+
+    let format = Format::from_path(path.as_ref());
+    let mut config = format
+        .parse(&contents)
+        .with_context(|| format!("Failed to parse {} config from: {:?}", format.name(), path.as_ref()))?;
+
+    // VS Code の設定ファイルに含まれるHTTPなどのリモートサーバーは起動できないため除外する
+    config.servers.retain(|name, server| match server.transport.as_deref() {
+        None | Some("stdio") => true,
+        Some(transport) => {
+            warn!("Skipping server '{}': '{}' servers cannot be started by the runner", name, transport);
+            false
+        }
+    });
+
+    // ${...} 形式の参照を展開する（相対パスは設定ファイルのディレクトリから解決）
+    let base_dir = path.as_ref().parent().unwrap_or(Path::new(""));
+    interpolate_servers(&mut config, base_dir)?;
     
     Ok(config)
 }
@@ -66,18 +158,22 @@ fn merge_env_vars(config: &mut Config) {
             .map(String::from)
             .collect::<Vec<_>>();
         
-        let mut env_vars = std::collections::HashMap::new();
-        for (key, value) in env::vars() {
-            if key != "PROGRAM" && key != "ARGS" && key != "HOST" && key != "PORT" && key != "CONFIG_FILE" {
-                env_vars.insert(key, value);
-            }
-        }
+        // 他のサーバー向けの秘密情報などを渡さないよう、ENV_INHERIT に列挙した環境変数のみを引き継ぐ
+        let env_inherit = env::var("ENV_INHERIT")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
         
         // "env" という名前でサーバーを追加
         config.servers.insert("env".to_string(), ServerConfig {
             command: program,
             args,
-            env: env_vars,
+            env_clear: true,
+            env_inherit,
+            ..Default::default()
         });
         
         // デフォルトサーバーが設定されていない場合、環境変数から設定したサーバーをデフォルトに
@@ -94,21 +190,221 @@ fn validate_config(config: &Config) -> Result<()> {
         return Err(anyhow::anyhow!("No server configurations found"));
     }
     
-    // デフォルトサーバーが指定されていない場合は先頭のサーバーをデフォルトとする
-    if config.default_server.is_none() && !config.servers.is_empty() {
-        let first_server = config.servers.keys().next().unwrap().clone();
-        warn!("No default server specified, using first server: {}", first_server);
+    // デフォルトサーバーがない場合、サーバー名を指定しない接続は拒否される
+    if config.default_server.is_none() {
+        info!("No default server specified, clients must select a server by path or query");
     }
     
+    if let Some(ref gateway) = config.gateway {
+        validate_gateway(config, gateway)?;
+    }
+
+    for (name, server) in &config.servers {
+        if server.command.is_empty() {
+            return Err(anyhow::anyhow!("Server '{}': command is required", name));
+        }
+        validate_sandbox(name, server)?;
+    }
+
     // デフォルトサーバーが存在するか確認
     if let Some(ref default_server) = config.default_server {
-        if !config.servers.contains_key(default_server) {
+        if !config.servers.contains_key(default_server) && !config.is_gateway(default_server) {
             return Err(anyhow::anyhow!(
                 "Default server '{}' not found in server configurations",
                 default_server
             ));
         }
     }
+
+    if let Some(ref auth) = config.auth {
+        validate_auth(config, auth)?;
+    }
+
+    // 管理APIは認証なしでは公開しない
+    if let Some(ref admin) = config.admin {
+        if admin.auth.tokens.is_empty() && admin.auth.api_keys.is_empty() {
+            return Err(anyhow::anyhow!("Admin API requires at least one token or API key"));
+        }
+        validate_api_keys(&admin.auth)?;
+    }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// 環境変数の引き継ぎ、リソース制限とサンドボックスの設定を検証する
+fn validate_sandbox(name: &str, server: &ServerConfig) -> Result<()> {
+    let limits = &server.limits;
+    let sandbox = &server.sandbox;
+    let has_limits = limits.memory_mb.is_some()
+        || limits.cpu_secs.is_some()
+        || limits.open_files.is_some()
+        || limits.processes.is_some();
+    if !cfg!(unix) && (has_limits || sandbox.uid.is_some() || sandbox.gid.is_some()) {
+        return Err(anyhow::anyhow!(
+            "Server '{}': resource limits and uid/gid are only supported on Unix",
+            name
+        ));
+    }
+    if !cfg!(target_os = "linux") && (sandbox.no_new_privs || sandbox.filesystem.is_some()) {
+        return Err(anyhow::anyhow!(
+            "Server '{}': no_new_privs and the filesystem sandbox are only supported on Linux",
+            name
+        ));
+    }
+
+    if !server.env_inherit.is_empty() && !server.env_clear {
+        return Err(anyhow::anyhow!(
+            "Server '{}': env_inherit requires env_clear",
+            name
+        ));
+    }
+
+    if let Some(ref filesystem) = sandbox.filesystem {
+        for path in filesystem.read_only.iter().chain(&filesystem.read_write) {
+            if !Path::new(path).is_absolute() {
+                return Err(anyhow::anyhow!(
+                    "Server '{}': sandbox path '{}' must be absolute",
+                    name, path
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// ゲートウェイ設定の妥当性を検証する
+fn validate_gateway(config: &Config, gateway: &GatewayConfig) -> Result<()> {
+    if config.servers.contains_key(&gateway.name) {
+        return Err(anyhow::anyhow!(
+            "Gateway name '{}' conflicts with a server of the same name",
+            gateway.name
+        ));
+    }
+    if gateway.separator.is_empty() {
+        return Err(anyhow::anyhow!("Gateway separator must not be empty"));
+    }
+
+    for server in &gateway.servers {
+        if !config.servers.contains_key(server) {
+            return Err(anyhow::anyhow!(
+                "Server '{}' listed in gateway configuration not found in server configurations",
+                server
+            ));
+        }
+    }
+
+    // サーバー名に区切り文字が含まれると名前を分解できない
+    let members = if gateway.servers.is_empty() {
+        config.servers.keys().collect::<Vec<_>>()
+    } else {
+        gateway.servers.iter().collect()
+    };
+    if let Some(server) = members.iter().find(|server| server.contains(&gateway.separator)) {
+        return Err(anyhow::anyhow!(
+            "Server name '{}' contains the gateway separator '{}'",
+            server,
+            gateway.separator
+        ));
+    }
+
+    Ok(())
+}
+
+/// 認証設定の妥当性を検証する
+fn validate_auth(config: &Config, auth: &AuthConfig) -> Result<()> {
+    if auth.tokens.is_empty() && auth.api_keys.is_empty() {
+        warn!("Authentication is enabled but no tokens or API keys are configured; all clients will be rejected");
+    }
+
+    validate_api_keys(auth)?;
+
+    // 許可されたサーバー名が存在するか確認
+    let allowed = auth
+        .tokens
+        .iter()
+        .filter_map(|token| token.servers.as_ref())
+        .chain(auth.api_keys.iter().filter_map(|key| key.servers.as_ref()))
+        .flatten();
+    for server in allowed {
+        if !config.servers.contains_key(server) && !config.is_gateway(server) {
+            return Err(anyhow::anyhow!(
+                "Server '{}' allowed in auth configuration not found in server configurations",
+                server
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// APIキーのハッシュが16進数のSHA-256であるか検証する
+fn validate_api_keys(auth: &AuthConfig) -> Result<()> {
+    for key in &auth.api_keys {
+        if key.sha256.len() != 64 || !key.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow::anyhow!(
+                "API key '{}' must be a hex-encoded SHA-256 hash",
+                key.name.as_deref().unwrap_or("unnamed")
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(format: Format, contents: &str) -> String {
+        format.parse(contents).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert!(matches!(Format::from_path(Path::new("config.toml")), Format::Toml));
+        assert!(matches!(Format::from_path(Path::new("config.YML")), Format::Yaml));
+        assert!(matches!(Format::from_path(Path::new("config.yaml")), Format::Yaml));
+        assert!(matches!(Format::from_path(Path::new("config.json")), Format::Json));
+        assert!(matches!(Format::from_path(Path::new("config")), Format::Json));
+    }
+
+    #[test]
+    fn line_column_is_one_based() {
+        let contents = "a = 1\nbé = 2\n";
+        assert_eq!(line_column(contents, 0), (1, 1));
+        assert_eq!(line_column(contents, 6), (2, 1));
+        assert_eq!(line_column(contents, 9), (2, 3));
+        assert_eq!(line_column(contents, 100), (3, 1));
+    }
+
+    #[test]
+    fn json_error_position() {
+        let contents = "{\n  \"servers\": {\n    \"a\": { \"command\": 1 }\n  }\n}\n";
+        assert_eq!(
+            parse_error(Format::Json, contents),
+            "invalid type: integer `1`, expected a string (line 3, column 23)"
+        );
+    }
+
+    #[test]
+    fn toml_error_position() {
+        let contents = "port = 8080\n\n[servers.a]\nargs = [\"x\"]\ncommand = 1\n";
+        assert_eq!(
+            parse_error(Format::Toml, contents),
+            "invalid type: integer `1`, expected a string (line 5, column 11)"
+        );
+
+        let contents = "port = 8080\n\n[servers.a]\ncommand = \"npx\"\nargs = [\"x\",\n";
+        assert_eq!(parse_error(Format::Toml, contents), "invalid array\nexpected `]` (line 6, column 1)");
+    }
+
+    #[test]
+    fn yaml_error_position() {
+        let contents = "port: 8080\nservers:\n  a:\n    command: npx\n    args: [x\n";
+        assert_eq!(parse_error(Format::Yaml, contents), "did not find expected ',' or ']' (line 6, column 1)");
+
+        let contents = "port: 8080\nservers:\n  a:\n    command: [npx]\n";
+        let error = parse_error(Format::Yaml, contents);
+        assert!(error.ends_with("(line 4, column 14)"), "{}", error);
+        assert_eq!(error.matches("line").count(), 1, "{}", error);
+    }
+}
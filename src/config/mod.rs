@@ -1,4 +1,7 @@
 pub mod model;
+mod interpolate;
 mod loader;
+mod reload;
 
-pub use loader::load_config;
\ No newline at end of file
+pub use loader::load_config;
+pub use reload::ConfigStore;
\ No newline at end of file
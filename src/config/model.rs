@@ -1,10 +1,15 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+
+/// 秘密情報を伏せる際に置き換える値
+const REDACTED: &str = "<redacted>";
 
 /// サーバー設定全体を表す構造体
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    /// 利用可能なサーバー設定のマップ
+    /// 利用可能なサーバー設定のマップ（Claude Desktop の設定ファイルの `mcpServers` も受け付ける）
+    #[serde(alias = "mcpServers")]
     pub servers: HashMap<String, ServerConfig>,
     /// デフォルトで使用するサーバー名
     #[serde(default)]
@@ -15,19 +20,471 @@ pub struct Config {
     /// WebSocketサーバーのポート（デフォルト: 8080）
     #[serde(default = "default_port")]
     pub port: u16,
+    /// HTTPトランスポートのリスナー設定（省略時は無効）
+    #[serde(default)]
+    pub http: Option<HttpConfig>,
+    /// WebSocketサーバーのTLS設定（省略時は平文の ws://）
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// クライアント認証の設定（省略時は認証なし）
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// 接続を許可するOriginヘッダーの値（省略時、ループバックで待ち受ける場合はlocalhostのみ許可）
+    #[serde(default)]
+    pub allowed_origins: Option<Vec<String>>,
+    /// 接続を許可するHostヘッダーの値（省略時、ループバックで待ち受ける場合はlocalhostのみ許可）
+    #[serde(default)]
+    pub allowed_hosts: Option<Vec<String>>,
+    /// 複数のサーバーを1つのMCPサーバーとして公開するゲートウェイ設定（省略時は無効）
+    #[serde(default)]
+    pub gateway: Option<GatewayConfig>,
+    /// Prometheusメトリクスのリスナー設定（省略時は無効）
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+    /// 管理APIのリスナー設定（省略時は無効）
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    /// `${input:<id>}` で参照する入力値（VS Code の設定ファイルとの互換用）
+    #[serde(default)]
+    pub inputs: Vec<InputConfig>,
+    /// 参照の展開で設定に入った秘密情報（ログや管理APIでは伏せる）
+    #[serde(skip)]
+    pub secrets: Secrets,
 }
 
-/// 個別のサーバー設定を表す構造体
+/// VS Code の設定ファイルの `inputs` に記述する入力値
+///
+/// VS Code では起動時に入力を求めるが、ランナーでは `INPUT_<ID>` 環境変数の値を使う。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputConfig {
+    /// `${input:<id>}` で参照する識別子
+    pub id: String,
+    /// 入力値の説明
+    #[serde(default)]
+    pub description: Option<String>,
+    /// 環境変数が設定されていない場合の値
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+impl InputConfig {
+    /// 値を読み込む環境変数名（英数字以外は `_` に置き換えて大文字にする）
+    pub fn env_var(&self) -> String {
+        let id: String = self
+            .id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        format!("INPUT_{}", id)
+    }
+}
+
+/// 環境変数やファイルから展開した秘密情報の値
+///
+/// `Debug` 出力には値を含めない。
+#[derive(Clone, Default)]
+pub struct Secrets(Vec<String>);
+
+impl Secrets {
+    /// 値を秘密情報として記録する（空文字列は無視する）
+    pub fn push(&mut self, value: &str) {
+        if !value.is_empty() && !self.0.iter().any(|secret| secret == value) {
+            self.0.push(value.to_string());
+        }
+    }
+
+    /// 文字列に含まれる秘密情報を伏せる
+    fn redact(&self, value: &mut String) {
+        for secret in &self.0 {
+            if value.contains(secret.as_str()) {
+                *value = value.replace(secret.as_str(), REDACTED);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secrets({} value(s))", self.0.len())
+    }
+}
+
+/// ゲートウェイの設定
+///
+/// ゲートウェイはサーバー名と同じように `name` で接続でき、各サーバーのツール・リソース・プロンプトを
+/// `<サーバー名><separator><名前>` の形式でまとめて公開する。
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayConfig {
+    /// 接続時に指定するゲートウェイ名（デフォルト: "gateway"）
+    #[serde(default = "default_gateway_name")]
+    pub name: String,
+    /// 集約するサーバー名（空の場合は全サーバー）
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// サーバー名と各項目名の区切り文字（デフォルト: "__"）
+    #[serde(default = "default_gateway_separator")]
+    pub separator: String,
+}
+
+/// クライアント認証の設定
+///
+/// 認証情報は `Authorization: Bearer <token>` ヘッダーまたは `token` クエリパラメータで渡す。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// 設定ファイルに平文で記述する静的なBearerトークン
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    /// SHA-256でハッシュ化して記述するAPIキー
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+}
+
+/// 静的なBearerトークン
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenConfig {
+    /// ログに表示する名前
+    #[serde(default)]
+    pub name: Option<String>,
+    /// トークンの値
+    pub token: String,
+    /// 接続を許可するサーバー名（省略時は全サーバー）
+    #[serde(default)]
+    pub servers: Option<Vec<String>>,
+}
+
+/// ハッシュ化されたAPIキー
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyConfig {
+    /// ログに表示する名前
+    #[serde(default)]
+    pub name: Option<String>,
+    /// APIキーのSHA-256ハッシュ（16進数）
+    pub sha256: String,
+    /// 接続を許可するサーバー名（省略時は全サーバー）
+    #[serde(default)]
+    pub servers: Option<Vec<String>>,
+}
+
+/// WebSocketサーバーをTLS（wss://）で提供するための設定
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM形式の証明書チェーンのパス
+    pub cert_path: String,
+    /// PEM形式の秘密鍵のパス
+    pub key_path: String,
+    /// クライアント証明書を検証するCA証明書のパス（指定時は相互TLS）
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+}
+
+/// セッションの一覧・操作と設定の確認を行う管理APIのリスナー設定
+///
+/// 認証情報は `Authorization: Bearer <token>` ヘッダーでのみ受け付ける。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// 管理APIのホスト（デフォルト: "127.0.0.1"）
+    #[serde(default = "default_admin_host")]
+    pub host: String,
+    /// 管理APIのポート（デフォルト: 9091）
+    #[serde(default = "default_admin_port")]
+    pub port: u16,
+    /// 管理APIの認証情報（トークンまたはAPIキーが1つ以上必要、`servers` は無視される）
+    #[serde(flatten)]
+    pub auth: AuthConfig,
+}
+
+/// Prometheusメトリクス（`/metrics`）のリスナー設定
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// メトリクスリスナーのホスト（デフォルト: "0.0.0.0"）
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// メトリクスリスナーのポート（デフォルト: 9090）
+    #[serde(default = "default_metrics_port")]
+    pub port: u16,
+}
+
+/// MCP Streamable HTTPトランスポートのリスナー設定
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// HTTPリスナーのホスト（デフォルト: "0.0.0.0"）
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// HTTPリスナーのポート（デフォルト: 8081）
+    #[serde(default = "default_http_port")]
+    pub port: u16,
+}
+
+/// 個別のサーバー設定を表す構造体
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct ServerConfig {
     /// 実行するコマンド
+    #[serde(default)]
     pub command: String,
+    /// VS Code の設定ファイルのトランスポート種別（"stdio" 以外のサーバーは読み込み時に除外する）
+    #[serde(default, rename = "type", skip_serializing)]
+    pub transport: Option<String>,
     /// コマンドに渡す引数
     #[serde(default)]
     pub args: Vec<String>,
     /// プロセスに渡す環境変数
     #[serde(default)]
     pub env: HashMap<String, String>,
+    /// ランナーの環境変数を引き継がない（`env_inherit` に指定したものと `env` のみを渡す、デフォルト: false）
+    #[serde(default)]
+    pub env_clear: bool,
+    /// `env_clear` の場合にランナーから引き継ぐ環境変数名のパターン（`*` と `?` が使える）
+    #[serde(default)]
+    pub env_inherit: Vec<String>,
+    /// ランナーから引き継がない環境変数名のパターン（`env` で指定したものは削除されない）
+    #[serde(default)]
+    pub env_remove: Vec<String>,
+    /// 子プロセスの起動モード（デフォルト: "isolated"）
+    #[serde(default)]
+    pub mode: ServerMode,
+    /// 子プロセスが終了した際の再起動設定
+    #[serde(default)]
+    pub restart: RestartConfig,
+    /// クライアントに公開するツールのポリシー
+    #[serde(default)]
+    pub tools: ToolPolicy,
+    /// リクエストのタイムアウト設定
+    #[serde(default)]
+    pub timeout: TimeoutConfig,
+    /// 子プロセスの標準エラー出力の扱い
+    #[serde(default)]
+    pub stderr: StderrConfig,
+    /// 子プロセスの停止手順の設定
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    /// 子プロセスのリソース制限（Unixのみ）
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// 子プロセスの実行ユーザーや作業ディレクトリ、ファイルシステムの制限
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+}
+
+/// 子プロセスのリソース制限
+///
+/// `setrlimit` で子プロセスに設定され、子プロセスが起動したプロセスにも引き継がれる。
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    /// アドレス空間の上限（MiB、RLIMIT_AS）
+    #[serde(default)]
+    pub memory_mb: Option<u64>,
+    /// CPU時間の上限（秒、RLIMIT_CPU）。超えるとSIGXCPU、さらに1秒後にSIGKILLで停止する
+    #[serde(default)]
+    pub cpu_secs: Option<u64>,
+    /// 同時に開けるファイル数の上限（RLIMIT_NOFILE）
+    #[serde(default)]
+    pub open_files: Option<u64>,
+    /// 実行ユーザーが持てるプロセス数の上限（RLIMIT_NPROC）
+    #[serde(default)]
+    pub processes: Option<u64>,
+}
+
+/// 子プロセスのサンドボックス設定
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    /// 子プロセスを実行するユーザーID（Unixのみ、ランナーに権限が必要）
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// 子プロセスを実行するグループID（Unixのみ、ランナーに権限が必要）
+    #[serde(default)]
+    pub gid: Option<u32>,
+    /// 子プロセスの作業ディレクトリ（省略時はランナーの作業ディレクトリ）
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// `no_new_privs` を設定し、setuidプログラムなどによる権限の昇格を禁止する（Linuxのみ、デフォルト: false）
+    #[serde(default)]
+    pub no_new_privs: bool,
+    /// Landlockによるファイルシステムへのアクセス制限（Linux 5.13以降、`no_new_privs` も有効になる）
+    #[serde(default)]
+    pub filesystem: Option<FilesystemSandbox>,
+}
+
+/// 子プロセスがアクセスできるパス
+///
+/// 指定したパス以下にのみアクセスでき、それ以外のファイルやディレクトリへのアクセスは拒否される。
+/// 子プロセスの実行ファイルや共有ライブラリのあるディレクトリも指定する必要がある。
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct FilesystemSandbox {
+    /// 読み取りと実行のみを許可するパス（絶対パス）
+    #[serde(default)]
+    pub read_only: Vec<String>,
+    /// 読み書きを許可するパス（絶対パス）
+    #[serde(default)]
+    pub read_write: Vec<String>,
+}
+
+/// 子プロセスの停止手順の設定
+///
+/// 子プロセスは標準入力を閉じて自発的な終了を待ち、終了しなければSIGTERM、最後にSIGKILLで停止する。
+/// ランナーの終了時は、その前に処理中のリクエストへの応答を待つ。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShutdownConfig {
+    /// ランナーの終了時に処理中のリクエストへの応答を待つ時間（ミリ秒、デフォルト: 10000）
+    #[serde(default = "default_grace_period_ms")]
+    pub grace_period_ms: u64,
+    /// 標準入力を閉じてから自発的な終了を待つ時間（ミリ秒、デフォルト: 5000）
+    #[serde(default = "default_exit_timeout_ms")]
+    pub exit_timeout_ms: u64,
+    /// SIGTERMを送ってからSIGKILLを送るまでの時間（ミリ秒、デフォルト: 5000）
+    #[serde(default = "default_kill_timeout_ms")]
+    pub kill_timeout_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            grace_period_ms: default_grace_period_ms(),
+            exit_timeout_ms: default_exit_timeout_ms(),
+            kill_timeout_ms: default_kill_timeout_ms(),
+        }
+    }
+}
+
+/// 子プロセスの標準エラー出力の設定
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StderrConfig {
+    /// 標準エラー出力の各行を `notifications/message` としてクライアントへ転送するか（デフォルト: false）
+    #[serde(default)]
+    pub forward: bool,
+    /// 直近の標準エラー出力を保持する行数（デフォルト: 100）
+    #[serde(default = "default_stderr_buffer_lines")]
+    pub buffer_lines: usize,
+}
+
+impl Default for StderrConfig {
+    fn default() -> Self {
+        Self {
+            forward: false,
+            buffer_lines: default_stderr_buffer_lines(),
+        }
+    }
+}
+
+/// リクエストのタイムアウト設定
+///
+/// タイムアウトしたリクエストにはクライアントへエラーを返し、子プロセスへ `notifications/cancelled` を送る。
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    /// 全リクエストに適用するタイムアウト（ミリ秒、省略時はタイムアウトなし）
+    #[serde(default)]
+    pub request_ms: Option<u64>,
+    /// メソッドごとのタイムアウト（ミリ秒、request_ms より優先）
+    #[serde(default)]
+    pub methods: HashMap<String, u64>,
+}
+
+/// ツールの許可・拒否ポリシー
+///
+/// パターンはグロブ形式（`*` は任意の文字列、`?` は任意の1文字）で、denyはallowより優先される。
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ToolPolicy {
+    /// 許可するツール名のパターン（空の場合は全て許可）
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// 拒否するツール名のパターン
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// 子プロセスをクライアント間でどのように割り当てるか
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerMode {
+    /// 接続ごとに専用の子プロセスを起動する
+    #[default]
+    Isolated,
+    /// 1つの子プロセスを全クライアントで共有し、JSON-RPCのIDを書き換えて多重化する
+    Shared,
+}
+
+/// 子プロセスの再起動設定
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestartConfig {
+    /// 再起動ポリシー（デフォルト: "never"）
+    #[serde(default)]
+    pub policy: RestartPolicy,
+    /// 連続して再起動を試みる最大回数（デフォルト: 5）
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// 最初の再起動までの待機時間（ミリ秒、デフォルト: 500）
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// 待機時間の上限（ミリ秒、デフォルト: 30000）
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// 待機時間に加えるランダムな揺らぎの割合（0.0〜1.0、デフォルト: 0.2）
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+}
+
+/// どのような終了で子プロセスを再起動するか
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// 再起動しない
+    #[default]
+    Never,
+    /// 異常終了した場合のみ再起動する
+    OnFailure,
+    /// 終了した場合は常に再起動する
+    Always,
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            policy: RestartPolicy::default(),
+            max_retries: default_max_retries(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            jitter: default_jitter(),
+        }
+    }
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_ms() -> u64 {
+    30_000
+}
+
+fn default_jitter() -> f64 {
+    0.2
+}
+
+fn default_grace_period_ms() -> u64 {
+    10_000
+}
+
+fn default_exit_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_kill_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_stderr_buffer_lines() -> usize {
+    100
+}
+
+fn default_gateway_name() -> String {
+    "gateway".to_string()
+}
+
+fn default_gateway_separator() -> String {
+    "__".to_string()
 }
 
 fn default_host() -> String {
@@ -38,6 +495,61 @@ fn default_port() -> u16 {
     8080
 }
 
+fn default_http_port() -> u16 {
+    8081
+}
+
+fn default_metrics_port() -> u16 {
+    9090
+}
+
+fn default_admin_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_admin_port() -> u16 {
+    9091
+}
+
+impl Config {
+    /// 指定した名前がゲートウェイを指しているかどうか
+    pub fn is_gateway(&self, name: &str) -> bool {
+        self.gateway.as_ref().is_some_and(|gateway| gateway.name == name)
+    }
+
+    /// 秘密情報（トークン、APIキーのハッシュ、環境変数の値、展開した秘密情報）を伏せた設定のコピーを返す
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        for server in config.servers.values_mut() {
+            for value in server.env.values_mut() {
+                *value = REDACTED.to_string();
+            }
+            self.secrets.redact(&mut server.command);
+            for arg in &mut server.args {
+                self.secrets.redact(arg);
+            }
+        }
+        if let Some(auth) = &mut config.auth {
+            auth.redact();
+        }
+        if let Some(admin) = &mut config.admin {
+            admin.auth.redact();
+        }
+        config
+    }
+}
+
+impl AuthConfig {
+    fn redact(&mut self) {
+        for token in &mut self.tokens {
+            token.token = REDACTED.to_string();
+        }
+        for key in &mut self.api_keys {
+            key.sha256 = REDACTED.to_string();
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -45,6 +557,16 @@ impl Default for Config {
             default_server: None,
             host: default_host(),
             port: default_port(),
+            http: None,
+            tls: None,
+            auth: None,
+            allowed_origins: None,
+            allowed_hosts: None,
+            gateway: None,
+            metrics: None,
+            admin: None,
+            inputs: Vec::new(),
+            secrets: Secrets::default(),
         }
     }
 }
@@ -62,7 +584,7 @@ pub fn example_config() -> Config {
             "/Users/yonaka/workspace".to_string(),
             "/Users/yonaka/mcp-servers".to_string(),
         ],
-        env: HashMap::new(),
+        ..Default::default()
     };
     
     // github サーバーの設定
@@ -72,7 +594,7 @@ pub fn example_config() -> Config {
             "-y".to_string(),
             "@modelcontextprotocol/server-github".to_string(),
         ],
-        env: HashMap::new(),
+        ..Default::default()
     };
     github_server.env.insert("GITHUB_PERSONAL_ACCESS_TOKEN".to_string(), "token_value".to_string());
     
@@ -84,5 +606,54 @@ pub fn example_config() -> Config {
         default_server: Some("filesystem".to_string()),
         host: "0.0.0.0".to_string(),
         port: 8080,
+        http: None,
+        tls: None,
+        auth: None,
+        allowed_origins: None,
+        allowed_hosts: None,
+        gateway: None,
+        metrics: None,
+        admin: None,
+        inputs: Vec::new(),
+        secrets: Secrets::default(),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn redacted_hides_secrets() {
+        let mut config: Config = serde_json::from_value(json!({
+            "servers": {
+                "github": {
+                    "command": "/opt/s3cret/bin/server",
+                    "args": ["--token=s3cret", "--token2=s3cret", "--verbose"],
+                    "env": { "GITHUB_TOKEN": "plain" }
+                }
+            },
+            "auth": {
+                "tokens": [{ "token": "client-token" }],
+                "api_keys": [{ "sha256": "abc123" }]
+            }
+        }))
+        .unwrap();
+        config.secrets.push("s3cret");
+        config.secrets.push("");
+
+        let redacted = config.redacted();
+        let server = &redacted.servers["github"];
+        assert_eq!(server.command, "/opt/<redacted>/bin/server");
+        assert_eq!(server.args, ["--token=<redacted>", "--token2=<redacted>", "--verbose"]);
+        assert_eq!(server.env["GITHUB_TOKEN"], "<redacted>");
+        let auth = redacted.auth.unwrap();
+        assert_eq!(auth.tokens[0].token, "<redacted>");
+        assert_eq!(auth.api_keys[0].sha256, "<redacted>");
+
+        // 元の設定は変更しない
+        assert_eq!(config.servers["github"].args[0], "--token=s3cret");
+        assert_eq!(format!("{:?}", config.secrets), "Secrets(1 value(s))");
+    }
+}
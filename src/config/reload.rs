@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use crate::config::loader::load_config;
+use crate::config::model::Config;
+use crate::constants::CONFIG_RELOAD_INTERVAL;
+
+/// 実行中に差し替え可能な設定
+///
+/// 再読み込みした設定は新しい接続にのみ適用され、既存のセッションは切断されるまで
+/// 元の子プロセスを使い続ける。
+pub struct ConfigStore {
+    /// 読み込んだ設定ファイルのパス（環境変数のみで設定した場合はなし）
+    path: Option<String>,
+    current: RwLock<Arc<Config>>,
+    modified: RwLock<Option<SystemTime>>,
+}
+
+impl ConfigStore {
+    /// 設定を読み込む
+    pub fn load(config_path: Option<&str>) -> Result<Arc<Self>> {
+        let path = config_path.map(String::from).or_else(|| env::var("CONFIG_FILE").ok());
+        let config = load_config(config_path)?;
+        Ok(Arc::new(Self {
+            modified: RwLock::new(path.as_deref().and_then(last_modified)),
+            path,
+            current: RwLock::new(Arc::new(config)),
+        }))
+    }
+
+    /// 現在の設定を返す
+    pub fn current(&self) -> Arc<Config> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    /// 設定ファイルを読み直して差し替える
+    ///
+    /// 読み込みや検証に失敗した場合は現在の設定をそのまま使い続ける。
+    pub fn reload(&self) -> Result<()> {
+        let path = self.path.as_deref().context("No config file to reload")?;
+        let modified = last_modified(path);
+        let config = load_config(Some(path))?;
+
+        let current = self.current();
+        for section in restart_required(&current, &config) {
+            warn!("Changes to '{}' take effect only after a restart", section);
+        }
+
+        *self.current.write().unwrap() = Arc::new(config);
+        *self.modified.write().unwrap() = modified;
+        info!("Configuration reloaded from {}", path);
+        Ok(())
+    }
+
+    /// 再読み込みを行い、失敗した場合はログに記録する
+    pub fn reload_or_log(&self) {
+        if let Err(e) = self.reload() {
+            error!("Failed to reload configuration: {:#}", e);
+        }
+    }
+
+    /// 設定ファイルが変更されるたびに再読み込みする
+    pub fn watch(self: &Arc<Self>) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        let store = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CONFIG_RELOAD_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let modified = last_modified(&path);
+                if modified == *store.modified.read().unwrap() {
+                    continue;
+                }
+                debug!("Config file changed, reloading");
+                store.reload_or_log();
+                // 失敗した場合もファイルが再び変更されるまでは再試行しない
+                *store.modified.write().unwrap() = modified;
+            }
+        });
+    }
+}
+
+/// 起動時にのみ反映される設定のうち、変更されたものの名前を返す
+fn restart_required(current: &Config, new: &Config) -> Vec<&'static str> {
+    let mut sections = Vec::new();
+    if current.host != new.host || current.port != new.port {
+        sections.push("host/port");
+    }
+    if current.http != new.http {
+        sections.push("http");
+    }
+    if current.tls != new.tls {
+        sections.push("tls");
+    }
+    if current.metrics != new.metrics {
+        sections.push("metrics");
+    }
+    let admin_addr = |config: &Config| config.admin.as_ref().map(|admin| (admin.host.clone(), admin.port));
+    if admin_addr(current) != admin_addr(new) {
+        sections.push("admin");
+    }
+    sections
+}
+
+fn last_modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn base() -> Value {
+        json!({
+            "servers": {
+                "github": { "command": "github-server", "args": ["--stdio"], "env": { "TOKEN": "a" } }
+            },
+            "host": "127.0.0.1",
+            "port": 8080,
+            "http": { "port": 8081 },
+            "metrics": { "port": 9090 },
+            "admin": { "port": 9091, "tokens": [{ "token": "admin" }] },
+            "auth": { "tokens": [{ "token": "client" }] }
+        })
+    }
+
+    /// `base()` の一部を書き換えた設定と比較する
+    fn changed(edit: impl FnOnce(&mut Value)) -> Vec<&'static str> {
+        let current: Config = serde_json::from_value(base()).unwrap();
+        let mut new = base();
+        edit(&mut new);
+        restart_required(&current, &serde_json::from_value(new).unwrap())
+    }
+
+    #[test]
+    fn unchanged_config_needs_nothing() {
+        assert!(changed(|_| {}).is_empty());
+    }
+
+    #[test]
+    fn live_changes_need_no_restart() {
+        let edits: [fn(&mut Value); 8] = [
+            |config| config["servers"]["github"]["args"] = json!(["--verbose"]),
+            |config| config["servers"]["github"]["env"]["TOKEN"] = json!("b"),
+            |config| config["servers"]["other"] = json!({ "command": "other-server" }),
+            |config| config["default_server"] = json!("github"),
+            |config| config["auth"]["tokens"][0]["token"] = json!("rotated"),
+            |config| config["admin"]["tokens"][0]["token"] = json!("rotated"),
+            |config| config["allowed_origins"] = json!(["https://example.com"]),
+            |config| config["gateway"] = json!({ "name": "all" }),
+        ];
+        for edit in edits {
+            assert!(changed(edit).is_empty());
+        }
+    }
+
+    #[test]
+    fn listener_changes_need_a_restart() {
+        assert_eq!(changed(|config| config["port"] = json!(8000)), ["host/port"]);
+        assert_eq!(changed(|config| config["host"] = json!("0.0.0.0")), ["host/port"]);
+        assert_eq!(changed(|config| config["http"]["port"] = json!(8001)), ["http"]);
+        assert_eq!(changed(|config| config["metrics"] = Value::Null), ["metrics"]);
+        assert_eq!(changed(|config| config["admin"]["port"] = json!(9000)), ["admin"]);
+        assert_eq!(
+            changed(|config| config["tls"] = json!({ "cert_path": "cert.pem", "key_path": "key.pem" })),
+            ["tls"]
+        );
+    }
+
+    #[test]
+    fn every_changed_section_is_reported() {
+        let sections = changed(|config| {
+            config["port"] = json!(8000);
+            config["http"] = Value::Null;
+            config["admin"]["host"] = json!("0.0.0.0");
+            config["servers"]["github"]["args"] = json!([]);
+        });
+        assert_eq!(sections, ["host/port", "http", "admin"]);
+    }
+}
@@ -1,4 +1,46 @@
 /// Size of the message buffer for communication channels.
 /// This value affects the capacity of mpsc channels used for
 /// process and WebSocket communication.
-pub const MESSAGE_BUFFER_SIZE: usize = 100;
\ No newline at end of file
+pub const MESSAGE_BUFFER_SIZE: usize = 100;
+
+/// A restarted child that stays up at least this long is considered healthy
+/// again, resetting the consecutive restart counter.
+pub const RESTART_RESET_AFTER: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How long to wait for the output of an exited child to be drained
+/// before its replacement is started.
+pub const OUTPUT_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Request id used when replaying a client's initialize request to a
+/// restarted child; the response carrying it is never forwarded.
+pub const REPLAY_REQUEST_ID: &str = "mcp-server-runner/replay-initialize";
+
+/// How long to wait for a restarted child to answer the replayed initialize request.
+pub const HANDSHAKE_REPLAY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// HTTP transport sessions without any request for this long are closed.
+pub const HTTP_SESSION_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+
+/// How often the TLS certificate and key files are checked for changes.
+pub const TLS_RELOAD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How long a late response to a timed out request is still recognized and dropped.
+pub const LATE_RESPONSE_WINDOW: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// How often the config file is checked for changes.
+pub const CONFIG_RELOAD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often a draining child is checked for requests still awaiting a response.
+pub const DRAIN_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// How long to wait at shutdown for connections to deliver their close frames.
+pub const CONNECTION_CLOSE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often a stopped child's process group is checked for remaining processes.
+pub const PROCESS_GROUP_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// How long processes of a group sent SIGKILL are given to disappear.
+pub const PROCESS_GROUP_KILL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// MCP protocol version the gateway asks for when it initializes its members.
+pub const GATEWAY_PROTOCOL_VERSION: &str = "2025-03-26";
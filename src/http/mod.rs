@@ -0,0 +1,243 @@
+mod sse;
+mod streamable;
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{HeaderValue, AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, HOST, ORIGIN, WWW_AUTHENTICATE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::auth::{authenticate, AuthError};
+use crate::config::ConfigStore;
+use crate::jsonrpc;
+use crate::metrics::{metrics, Direction, Transport};
+use crate::origin::OriginPolicy;
+use crate::session::SessionRegistry;
+use self::sse::LegacySse;
+use self::streamable::StreamableHttp;
+
+/// Response body type used by every HTTP endpoint of the runner.
+pub type Body = UnsyncBoxBody<Bytes, Infallible>;
+
+/// MCP transports served over plain HTTP next to the WebSocket server:
+/// Streamable HTTP on `/mcp` and the legacy HTTP+SSE transport on `/sse`.
+struct HttpTransport {
+    config: Arc<ConfigStore>,
+    streamable: Arc<StreamableHttp>,
+    sse: Arc<LegacySse>,
+}
+
+/// Runs the HTTP transport listener until the listener fails.
+pub async fn run_http_server(
+    listener: TcpListener,
+    config: Arc<ConfigStore>,
+    registry: Arc<SessionRegistry>,
+) -> Result<()> {
+    let streamable = StreamableHttp::new(Arc::clone(&config), Arc::clone(&registry));
+    streamable.start_reaper();
+    let sse = LegacySse::new(Arc::clone(&config), registry);
+
+    let transport = Arc::new(HttpTransport { config, streamable, sse });
+    serve(listener, move |request, addr| {
+        let transport = Arc::clone(&transport);
+        async move { transport.handle(request, addr).await }
+    })
+    .await
+}
+
+impl HttpTransport {
+    async fn handle(&self, request: Request<Incoming>, addr: SocketAddr) -> Response<Body> {
+        let path = request.uri().path().to_string();
+        debug!("HTTP {} {} from {}", request.method(), path, addr);
+
+        let transport = if strip_route(&path, "/mcp").is_some() {
+            Some(Transport::StreamableHttp)
+        } else if strip_route(&path, "/sse").is_some() || path == "/messages" {
+            Some(Transport::Sse)
+        } else {
+            None
+        };
+        let reject = || {
+            if let Some(transport) = transport {
+                metrics().connection(transport, false);
+            }
+        };
+
+        let config = self.config.current();
+        let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok());
+        let bind_host = config.http.as_ref().map_or(config.host.as_str(), |http| http.host.as_str());
+        if let Err(e) = OriginPolicy::new(&config, bind_host).check(header(ORIGIN), header(HOST)) {
+            reject();
+            return text(StatusCode::FORBIDDEN, &e.to_string());
+        }
+
+        let grant = match authenticate(config.auth.as_ref(), header(AUTHORIZATION), request.uri().query()) {
+            Ok(grant) => grant,
+            Err(e) => {
+                warn!("HTTP request from {} rejected: {}", addr, e);
+                reject();
+                return unauthorized(&e);
+            }
+        };
+
+        if let Some(rest) = strip_route(&path, "/mcp") {
+            return self.streamable.handle(request, addr, rest, &grant).await;
+        }
+        if let Some(rest) = strip_route(&path, "/sse") {
+            return self.sse.connect(request, addr, rest, &grant).await;
+        }
+        if path == "/messages" {
+            return self.sse.post(request).await;
+        }
+        text(StatusCode::NOT_FOUND, "Not Found")
+    }
+}
+
+/// Returns the rest of `path` if it is `prefix` itself or a sub-path of it.
+fn strip_route<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    path.strip_prefix(prefix)
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Serves HTTP/1.1 connections accepted from `listener` with `handler`.
+pub(crate) async fn serve<H, F>(listener: TcpListener, handler: H) -> Result<()>
+where
+    H: Fn(Request<Incoming>, SocketAddr) -> F + Clone + Send + 'static,
+    F: Future<Output = Response<Body>> + Send + 'static,
+{
+    info!("HTTP listener started on {}", listener.local_addr()?);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let response = handler(request, addr);
+                async move { Ok::<_, Infallible>(response.await) }
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("HTTP connection from {} ended with error: {}", addr, e);
+            }
+        });
+    }
+}
+
+/// Builds a response with a complete body.
+pub(crate) fn full(status: StatusCode, content_type: &str, body: impl Into<Bytes>) -> Response<Body> {
+    let mut response = Response::new(Full::new(body.into()).boxed_unsync());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, content_type.parse().expect("valid content type"));
+    response
+}
+
+/// Builds a plain-text response.
+pub(crate) fn text(status: StatusCode, message: &str) -> Response<Body> {
+    full(status, "text/plain; charset=utf-8", message.to_string())
+}
+
+/// Builds a JSON response.
+pub(crate) fn json(status: StatusCode, value: &serde_json::Value) -> Response<Body> {
+    full(status, "application/json", value.to_string())
+}
+
+/// Builds a JSON response carrying JSON-RPC messages for the client,
+/// counted as outgoing messages of `transport`.
+pub(crate) fn reply_json(transport: Transport, status: StatusCode, value: &serde_json::Value) -> Response<Body> {
+    let body = value.to_string();
+    metrics().message(transport, Direction::Out, body.len());
+    full(status, "application/json", body)
+}
+
+/// Formats a JSON-RPC message for the client as a `message` event,
+/// counted as an outgoing message of `transport`.
+pub(crate) fn message_event(transport: Transport, data: &str) -> String {
+    metrics().message(transport, Direction::Out, data.len());
+    sse_event("message", data)
+}
+
+/// Builds the response for a request that failed authentication.
+pub(crate) fn unauthorized(error: &AuthError) -> Response<Body> {
+    if !error.is_challenge() {
+        return text(StatusCode::FORBIDDEN, &error.to_string());
+    }
+    let mut response = text(StatusCode::UNAUTHORIZED, &error.to_string());
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// A POSTed message that passed validation.
+pub(crate) struct Posted {
+    /// The message, or the valid members of a batch, to forward.
+    message: serde_json::Value,
+    /// Errors for the invalid members of a batch.
+    errors: Vec<serde_json::Value>,
+    batch: bool,
+}
+
+/// Parses a POSTed JSON-RPC message or batch.
+///
+/// Like on WebSocket, the valid members of a batch are kept and the invalid
+/// ones answered with errors. For a body with nothing left to forward, the
+/// error response(s) to answer with are returned instead.
+pub(crate) fn parse_message(body: &[u8]) -> Result<Posted, serde_json::Value> {
+    let frame = match std::str::from_utf8(body) {
+        Ok(text) => jsonrpc::parse_frame(text),
+        Err(e) => jsonrpc::Frame::parse_error(e),
+    };
+    if let Some(message) = frame.message {
+        return Ok(Posted { message, errors: frame.errors, batch: frame.batch });
+    }
+
+    debug!("Rejecting invalid JSON-RPC request body ({} error(s))", frame.errors.len());
+    Err(jsonrpc::reply(frame.errors, frame.batch).unwrap_or_default())
+}
+
+/// Builds a response without a body.
+pub(crate) fn empty(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Empty::new().boxed_unsync());
+    *response.status_mut() = status;
+    response
+}
+
+/// Builds a `text/event-stream` response from already formatted events.
+pub(crate) fn event_stream<S>(events: S) -> Response<Body>
+where
+    S: Stream<Item = String> + Send + 'static,
+{
+    let frames = events.map(|event| Ok(Frame::data(Bytes::from(event))));
+    let mut response = Response::new(BodyExt::boxed_unsync(StreamBody::new(frames)));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, "text/event-stream".parse().expect("valid content type"));
+    headers.insert(CACHE_CONTROL, "no-cache".parse().expect("valid cache control"));
+    response
+}
+
+/// Formats a single Server-Sent Event.
+pub(crate) fn sse_event(event: &str, data: &str) -> String {
+    let mut formatted = format!("event: {}\n", event);
+    for line in data.lines() {
+        formatted.push_str("data: ");
+        formatted.push_str(line);
+        formatted.push('\n');
+    }
+    formatted.push('\n');
+    formatted
+}
@@ -0,0 +1,209 @@
+use futures_util::{stream, StreamExt};
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::{Method, Request, Response, StatusCode};
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+use super::{event_stream, message_event, parse_message, text, unauthorized, Body, Posted};
+use crate::auth::Grant;
+use crate::batch::BatchReplies;
+use crate::config::model::ToolPolicy;
+use crate::config::ConfigStore;
+use crate::constants::MESSAGE_BUFFER_SIZE;
+use crate::jsonrpc;
+use crate::metrics::{metrics, Direction, Transport};
+use crate::routing::{query_param, resolve_server};
+use crate::session::{SessionId, SessionRegistry};
+use crate::state::SessionState;
+
+/// Path clients POST their messages to, announced in the `endpoint` event.
+const MESSAGES_PATH: &str = "/messages";
+
+/// The legacy MCP HTTP+SSE transport (`GET /sse` + `POST /messages`).
+///
+/// Each event stream is its own session: the child's stdout lines are sent
+/// as `message` events and the session ends when the stream is closed.
+pub struct LegacySse {
+    config: Arc<ConfigStore>,
+    registry: Arc<SessionRegistry>,
+    sessions: Mutex<HashMap<String, SseSession>>,
+}
+
+struct SseSession {
+    session_id: SessionId,
+    process_tx: mpsc::Sender<String>,
+    /// Delivers refused tool calls back to the event stream without keeping it open.
+    output_tx: mpsc::WeakSender<String>,
+    state: Arc<SessionState>,
+    tools: ToolPolicy,
+    batches: Arc<BatchReplies>,
+}
+
+/// Closes the session once its event stream is dropped.
+struct StreamGuard {
+    transport: Arc<LegacySse>,
+    id: String,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let transport = Arc::clone(&self.transport);
+        let id = std::mem::take(&mut self.id);
+        tokio::spawn(async move { transport.close(&id).await });
+    }
+}
+
+impl LegacySse {
+    pub fn new(config: Arc<ConfigStore>, registry: Arc<SessionRegistry>) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            registry,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Opens an event stream backed by a new session; `path` is the remainder after `/sse`.
+    pub async fn connect(
+        self: &Arc<Self>,
+        request: Request<Incoming>,
+        addr: SocketAddr,
+        path: &str,
+        grant: &Grant,
+    ) -> Response<Body> {
+        if request.method() != Method::GET {
+            return text(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+        }
+
+        let config = self.config.current();
+        let server_name = match resolve_server(path, request.uri().query(), &config) {
+            Ok(name) => name,
+            Err(e) => {
+                warn!("SSE connection rejected for {}: {}", addr, e);
+                metrics().connection(Transport::Sse, false);
+                return text(StatusCode::NOT_FOUND, &e.to_string());
+            }
+        };
+        if let Err(e) = grant.authorize(&server_name) {
+            metrics().connection(Transport::Sse, false);
+            return unauthorized(&e);
+        }
+
+        let (output_tx, output_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let reply_tx = output_tx.downgrade();
+        let handle = match self.registry.open(addr, &server_name, &config, output_tx).await {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Failed to start process for SSE session: {}", e);
+                metrics().connection(Transport::Sse, false);
+                return text(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start MCP server");
+            }
+        };
+
+        metrics().connection(Transport::Sse, true);
+        let state = Arc::clone(&handle.state);
+        let batches = Arc::new(BatchReplies::default());
+        let id = format!("{:032x}", rand::random::<u128>());
+        info!("SSE session {} started for {} (server: {})", id, addr, server_name);
+        self.sessions.lock().await.insert(
+            id.clone(),
+            SseSession {
+                session_id: handle.id,
+                process_tx: handle.process_tx,
+                output_tx: reply_tx,
+                state: handle.state,
+                tools: handle.tools,
+                batches: Arc::clone(&batches),
+            },
+        );
+
+        let endpoint = super::sse_event("endpoint", &format!("{}?sessionId={}", MESSAGES_PATH, id));
+        let guard = StreamGuard { transport: Arc::clone(self), id };
+        let messages = stream::unfold((output_rx, VecDeque::new(), guard), move |(mut output_rx, mut lines, guard)| {
+            let state = Arc::clone(&state);
+            let batches = Arc::clone(&batches);
+            async move {
+                while lines.is_empty() {
+                    lines.extend(batches.complete(output_rx.recv().await?));
+                }
+                let line = lines.pop_front()?;
+                state.count_sent();
+                Some((message_event(Transport::Sse, &line), (output_rx, lines, guard)))
+            }
+        });
+        event_stream(stream::once(async move { endpoint }).chain(messages))
+    }
+
+    /// Forwards a POSTed message to the child of the session named in the query.
+    /// Replies, including errors for an invalid body, go out on the session's event stream.
+    pub async fn post(&self, request: Request<Incoming>) -> Response<Body> {
+        if request.method() != Method::POST {
+            return text(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+        }
+        let Some(id) = request.uri().query().and_then(|query| query_param(query, "sessionId")) else {
+            return text(StatusCode::BAD_REQUEST, "Bad Request: sessionId query parameter is required");
+        };
+
+        let body = match request.into_body().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return text(StatusCode::BAD_REQUEST, &format!("Failed to read request body: {}", e)),
+        };
+        metrics().message(Transport::Sse, Direction::In, body.len());
+
+        let sessions = self.sessions.lock().await;
+        let Some(session) = sessions.get(&id) else {
+            return text(StatusCode::NOT_FOUND, "Session not found");
+        };
+        let (reply, forward) = match parse_message(&body) {
+            Ok(Posted { message, mut errors, batch }) => {
+                session.state.count_received();
+                session.state.record_client_message(&message);
+                let screened = session.tools.screen(message);
+                errors.extend(screened.rejected);
+                let mut forward = screened.forward;
+                // Responses and notifications still reach the child while it drains.
+                if session.state.is_going_away() {
+                    if let Some(message) = forward.take() {
+                        let (rest, refused) =
+                            jsonrpc::refuse_requests(message, jsonrpc::SHUTTING_DOWN, "Server is shutting down");
+                        errors.extend(refused);
+                        forward = rest;
+                    }
+                }
+                (session.batches.reply(forward.as_ref(), errors, batch), forward)
+            }
+            // Every reply on this transport goes over the event stream, even to an invalid body.
+            Err(errors) => {
+                session.state.count_received();
+                (Some(errors), None)
+            }
+        };
+        let process_tx = session.process_tx.clone();
+        let output_tx = session.output_tx.upgrade();
+        drop(sessions);
+
+        if let (Some(reply), Some(output_tx)) = (reply, output_tx) {
+            let _ = output_tx.send(reply.to_string()).await;
+        }
+        let Some(message) = forward else {
+            return text(StatusCode::ACCEPTED, "Accepted");
+        };
+
+        debug!("Forwarding message for SSE session {}", id);
+        if process_tx.send(message.to_string()).await.is_err() {
+            return text(StatusCode::NOT_FOUND, "Session not found");
+        }
+        text(StatusCode::ACCEPTED, "Accepted")
+    }
+
+    async fn close(&self, id: &str) {
+        let session = self.sessions.lock().await.remove(id);
+        if let Some(session) = session {
+            info!("SSE session {} closed", id);
+            self.registry.close(session.session_id).await;
+        }
+    }
+}
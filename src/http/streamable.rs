@@ -0,0 +1,441 @@
+use futures_util::{stream, StreamExt};
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, ACCEPT, ALLOW};
+use hyper::{Method, Request, Response, StatusCode};
+use log::{debug, error, info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{mpsc, Mutex};
+
+use super::{empty, event_stream, json, message_event, parse_message, reply_json, text, unauthorized, Body, Posted};
+use crate::auth::Grant;
+use crate::config::model::ToolPolicy;
+use crate::config::ConfigStore;
+use crate::constants::{HTTP_SESSION_IDLE_TIMEOUT, MESSAGE_BUFFER_SIZE};
+use crate::jsonrpc;
+use crate::metrics::{metrics, Direction, Transport};
+use crate::routing::resolve_server;
+use crate::session::{SessionId, SessionRegistry};
+use crate::state::SessionState;
+
+const SESSION_HEADER: &str = "mcp-session-id";
+
+/// The MCP Streamable HTTP transport served on `/mcp`.
+///
+/// Each `Mcp-Session-Id` maps onto a registry session with its own child
+/// process; JSON-RPC responses are routed back to the POST that carried
+/// the matching request.
+pub struct StreamableHttp {
+    config: Arc<ConfigStore>,
+    registry: Arc<SessionRegistry>,
+    sessions: Mutex<HashMap<String, Arc<HttpSession>>>,
+}
+
+/// A Streamable HTTP session and where its child's output should go.
+struct HttpSession {
+    id: String,
+    session_id: SessionId,
+    process_tx: mpsc::Sender<String>,
+    state: Arc<SessionState>,
+    tools: ToolPolicy,
+    routes: std::sync::Mutex<Routes>,
+    last_seen: std::sync::Mutex<Instant>,
+}
+
+#[derive(Default)]
+struct Routes {
+    /// Request ids and the streams waiting for their responses, keyed by serialized request id.
+    pending: HashMap<String, (Value, mpsc::Sender<Value>)>,
+    /// The stream opened with `GET`, used for server-initiated messages.
+    standalone: Option<mpsc::Sender<Value>>,
+    /// The most recent POST answered with an event stream, used when no `GET` stream is open.
+    latest: Option<mpsc::Sender<Value>>,
+}
+
+impl StreamableHttp {
+    pub fn new(config: Arc<ConfigStore>, registry: Arc<SessionRegistry>) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            registry,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Periodically closes sessions that have been idle for too long.
+    pub fn start_reaper(self: &Arc<Self>) {
+        let transport = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HTTP_SESSION_IDLE_TIMEOUT / 10);
+            loop {
+                interval.tick().await;
+                let idle: Vec<String> = transport
+                    .sessions
+                    .lock()
+                    .await
+                    .values()
+                    .filter(|session| session.is_idle())
+                    .map(|session| session.id.clone())
+                    .collect();
+                for id in idle {
+                    info!("Closing idle HTTP session {}", id);
+                    transport.close(&id).await;
+                }
+            }
+        });
+    }
+
+    /// Handles a request to `/mcp`; `path` is the remainder after the prefix.
+    pub async fn handle(
+        self: &Arc<Self>,
+        request: Request<Incoming>,
+        addr: SocketAddr,
+        path: &str,
+        grant: &Grant,
+    ) -> Response<Body> {
+        match *request.method() {
+            Method::POST => self.post(request, addr, path, grant).await,
+            Method::GET => self.get(request).await,
+            Method::DELETE => self.delete(request).await,
+            _ => {
+                let mut response = text(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+                response.headers_mut().insert(ALLOW, HeaderValue::from_static("GET, POST, DELETE"));
+                response
+            }
+        }
+    }
+
+    /// Forwards POSTed JSON-RPC messages and answers with the child's responses.
+    async fn post(
+        self: &Arc<Self>,
+        request: Request<Incoming>,
+        addr: SocketAddr,
+        path: &str,
+        grant: &Grant,
+    ) -> Response<Body> {
+        let accept = header(&request, ACCEPT.as_str()).unwrap_or_default();
+        let session_header = header(&request, SESSION_HEADER);
+        let query = request.uri().query().map(str::to_string);
+
+        let body = match request.into_body().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return text(StatusCode::BAD_REQUEST, &format!("Failed to read request body: {}", e)),
+        };
+        metrics().message(Transport::StreamableHttp, Direction::In, body.len());
+        let Posted { message, errors, batch } = match parse_message(&body) {
+            Ok(posted) => posted,
+            Err(errors) => return reply_json(Transport::StreamableHttp, StatusCode::BAD_REQUEST, &errors),
+        };
+
+        let request_ids = jsonrpc::request_ids(&message);
+        let initialize = match &message {
+            Value::Array(batch) => batch.iter().any(|message| jsonrpc::method(message) == Some("initialize")),
+            message => jsonrpc::method(message) == Some("initialize"),
+        };
+
+        let (session, created) = match session_header {
+            Some(id) => match self.find(&id).await {
+                Some(session) => (session, false),
+                None => return session_not_found(),
+            },
+            None if initialize => {
+                let created = self.create(addr, path, query.as_deref(), grant).await;
+                metrics().connection(Transport::StreamableHttp, created.is_ok());
+                match created {
+                    Ok(session) => (session, true),
+                    Err(response) => return response,
+                }
+            }
+            None => return text(StatusCode::BAD_REQUEST, "Bad Request: Mcp-Session-Id header is required"),
+        };
+        session.touch();
+        session.state.count_received();
+        session.state.record_client_message(&message);
+        let mut screened = session.tools.screen(message);
+        // Responses and notifications still reach the child while it drains.
+        if session.state.is_going_away() {
+            if let Some(message) = screened.forward.take() {
+                let (rest, refused) = jsonrpc::refuse_requests(message, jsonrpc::SHUTTING_DOWN, "Server is shutting down");
+                screened.rejected.extend(refused);
+                screened.forward = rest;
+            }
+        }
+
+        if request_ids.is_empty() {
+            if let Some(message) = screened.forward {
+                if session.process_tx.send(message.to_string()).await.is_err() {
+                    return session_not_found();
+                }
+            }
+            // Invalid members of a batch of notifications are still answered.
+            return match jsonrpc::reply(errors, batch) {
+                Some(errors) => reply_json(Transport::StreamableHttp, StatusCode::OK, &errors),
+                None => empty(StatusCode::ACCEPTED),
+            };
+        }
+
+        let streaming = wants_event_stream(&accept);
+        let (tx, rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        session.expect(&request_ids, tx, streaming);
+        for error in screened.rejected {
+            session.route(error).await;
+        }
+        if let Some(message) = screened.forward {
+            if session.process_tx.send(message.to_string()).await.is_err() {
+                return session_not_found();
+            }
+        }
+
+        let mut response = if streaming {
+            event_stream(response_events(rx, errors, request_ids.len()))
+        } else {
+            let responses = collect_responses(rx, errors, request_ids.len(), batch).await;
+            reply_json(Transport::StreamableHttp, StatusCode::OK, &responses)
+        };
+        if created {
+            let id = HeaderValue::from_str(&session.id).expect("session ids are valid header values");
+            response.headers_mut().insert(SESSION_HEADER, id);
+        }
+        response
+    }
+
+    /// Opens the standalone event stream for server-initiated messages.
+    async fn get(&self, request: Request<Incoming>) -> Response<Body> {
+        let accept = header(&request, ACCEPT.as_str()).unwrap_or_default();
+        if !accept.contains("text/event-stream") {
+            return text(StatusCode::NOT_ACCEPTABLE, "Not Acceptable: client must accept text/event-stream");
+        }
+        let session = match self.session_from(&request).await {
+            Ok(session) => session,
+            Err(response) => return response,
+        };
+
+        let (tx, mut rx) = mpsc::channel::<Value>(MESSAGE_BUFFER_SIZE);
+        session.routes.lock().unwrap().standalone = Some(tx);
+        session.touch();
+        debug!("Opened standalone event stream for HTTP session {}", session.id);
+
+        event_stream(stream::poll_fn(move |cx| {
+            rx.poll_recv(cx)
+                .map(|message| message.map(|message| message_event(Transport::StreamableHttp, &message.to_string())))
+        }))
+    }
+
+    /// Terminates a session at the client's request.
+    async fn delete(&self, request: Request<Incoming>) -> Response<Body> {
+        let session = match self.session_from(&request).await {
+            Ok(session) => session,
+            Err(response) => return response,
+        };
+        info!("HTTP session {} terminated by client", session.id);
+        self.close(&session.id).await;
+        empty(StatusCode::NO_CONTENT)
+    }
+
+    /// Starts a child for a new session and begins routing its output.
+    async fn create(
+        self: &Arc<Self>,
+        addr: SocketAddr,
+        path: &str,
+        query: Option<&str>,
+        grant: &Grant,
+    ) -> Result<Arc<HttpSession>, Response<Body>> {
+        let config = self.config.current();
+        let server_name = resolve_server(path, query, &config).map_err(|e| {
+            warn!("HTTP session rejected for {}: {}", addr, e);
+            text(StatusCode::NOT_FOUND, &e.to_string())
+        })?;
+        grant.authorize(&server_name).map_err(|e| unauthorized(&e))?;
+
+        let (output_tx, output_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let handle = self
+            .registry
+            .open(addr, &server_name, &config, output_tx)
+            .await
+            .map_err(|e| {
+                error!("Failed to start process for HTTP session: {}", e);
+                text(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start MCP server")
+            })?;
+
+        let session = Arc::new(HttpSession {
+            id: format!("{:032x}", rand::random::<u128>()),
+            session_id: handle.id,
+            process_tx: handle.process_tx,
+            state: handle.state,
+            tools: handle.tools,
+            routes: std::sync::Mutex::new(Routes::default()),
+            last_seen: std::sync::Mutex::new(Instant::now()),
+        });
+        info!(
+            "HTTP session {} started for {} (server: {})",
+            session.id, addr, server_name
+        );
+        self.sessions.lock().await.insert(session.id.clone(), Arc::clone(&session));
+        tokio::spawn(Arc::clone(self).dispatch(Arc::clone(&session), output_rx));
+
+        Ok(session)
+    }
+
+    /// Routes everything the child writes until its output ends.
+    async fn dispatch(self: Arc<Self>, session: Arc<HttpSession>, mut output_rx: mpsc::Receiver<String>) {
+        while let Some(text) = output_rx.recv().await {
+            session.state.count_sent();
+            let message: Value = match serde_json::from_str(&text) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Dropping unparsable output for HTTP session {}: {}", session.id, e);
+                    continue;
+                }
+            };
+            match message {
+                Value::Array(batch) => {
+                    for message in batch {
+                        session.route(message).await;
+                    }
+                }
+                message => session.route(message).await,
+            }
+        }
+
+        debug!("Output of HTTP session {} ended", session.id);
+        session.fail_pending("Session closed").await;
+        self.close(&session.id).await;
+    }
+
+    async fn find(&self, id: &str) -> Option<Arc<HttpSession>> {
+        self.sessions.lock().await.get(id).cloned()
+    }
+
+    async fn session_from(&self, request: &Request<Incoming>) -> Result<Arc<HttpSession>, Response<Body>> {
+        let id = header(request, SESSION_HEADER)
+            .ok_or_else(|| text(StatusCode::BAD_REQUEST, "Bad Request: Mcp-Session-Id header is required"))?;
+        self.find(&id).await.ok_or_else(session_not_found)
+    }
+
+    async fn close(&self, id: &str) {
+        let session = self.sessions.lock().await.remove(id);
+        if let Some(session) = session {
+            self.registry.close(session.session_id).await;
+        }
+    }
+}
+
+impl HttpSession {
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    fn is_idle(&self) -> bool {
+        let streaming = self
+            .routes
+            .lock()
+            .unwrap()
+            .standalone
+            .as_ref()
+            .is_some_and(|tx| !tx.is_closed());
+        !streaming && self.last_seen.lock().unwrap().elapsed() >= HTTP_SESSION_IDLE_TIMEOUT
+    }
+
+    /// Registers the stream that should receive the responses to `request_ids`.
+    fn expect(&self, request_ids: &[Value], tx: mpsc::Sender<Value>, streaming: bool) {
+        let mut routes = self.routes.lock().unwrap();
+        for id in request_ids {
+            routes.pending.insert(id.to_string(), (id.clone(), tx.clone()));
+        }
+        if streaming {
+            routes.latest = Some(tx);
+        }
+    }
+
+    /// Delivers one message from the child to the stream it belongs to.
+    async fn route(&self, message: Value) {
+        let target = {
+            let mut routes = self.routes.lock().unwrap();
+            if jsonrpc::is_response(&message) {
+                routes.pending.remove(&message["id"].to_string()).map(|(_, tx)| tx)
+            } else {
+                [&routes.standalone, &routes.latest]
+                    .into_iter()
+                    .flatten()
+                    .find(|tx| !tx.is_closed())
+                    .cloned()
+            }
+        };
+
+        match target {
+            Some(tx) => {
+                if tx.send(message).await.is_err() {
+                    debug!("HTTP client of session {} went away before delivery", self.id);
+                }
+            }
+            None => debug!("No open stream for message in HTTP session {}: {}", self.id, message),
+        }
+    }
+
+    /// Answers every request still waiting for a response with an error,
+    /// so no POST is left without its response once the child's output ends.
+    async fn fail_pending(&self, reason: &str) {
+        let pending: Vec<(Value, mpsc::Sender<Value>)> =
+            self.routes.lock().unwrap().pending.drain().map(|(_, route)| route).collect();
+        for (id, tx) in pending {
+            debug!("Failing request {} of HTTP session {}: {}", id, self.id, reason);
+            let _ = tx.send(jsonrpc::error_response(id, jsonrpc::INTERNAL_ERROR, reason)).await;
+        }
+    }
+}
+
+/// Answers a message for an unknown or closed session.
+fn session_not_found() -> Response<Body> {
+    let error = jsonrpc::error_response(Value::Null, jsonrpc::SESSION_NOT_FOUND, "Session not found");
+    json(StatusCode::NOT_FOUND, &error)
+}
+
+/// Streams `errors` and then the child's messages as events until every
+/// expected response has been sent.
+fn response_events(
+    rx: mpsc::Receiver<Value>,
+    errors: Vec<Value>,
+    expected: usize,
+) -> impl futures_util::Stream<Item = String> {
+    let errors = stream::iter(errors.into_iter().map(|error| message_event(Transport::StreamableHttp, &error.to_string())));
+    errors.chain(stream::unfold((rx, expected), |(mut rx, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let message = rx.recv().await?;
+        let remaining = remaining - usize::from(jsonrpc::is_response(&message));
+        Some((message_event(Transport::StreamableHttp, &message.to_string()), (rx, remaining)))
+    }))
+}
+
+/// Waits for every expected response and returns them, after `errors`, as a
+/// single JSON body.
+async fn collect_responses(mut rx: mpsc::Receiver<Value>, errors: Vec<Value>, expected: usize, batch: bool) -> Value {
+    let expected = errors.len() + expected;
+    let mut responses = errors;
+    while responses.len() < expected {
+        match rx.recv().await {
+            Some(message) if jsonrpc::is_response(&message) => responses.push(message),
+            Some(_) => {}
+            None => break,
+        }
+    }
+    jsonrpc::reply(responses, batch).unwrap_or_else(|| Value::Array(Vec::new()))
+}
+
+/// Prefers an event stream when the client accepts one, so notifications
+/// emitted while a request is in flight reach the client as well.
+fn wants_event_stream(accept: &str) -> bool {
+    accept.contains("text/event-stream")
+}
+
+fn header(request: &Request<Incoming>, name: &str) -> Option<String> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
@@ -0,0 +1,305 @@
+//! Helpers for inspecting and building JSON-RPC 2.0 messages.
+
+use serde_json::{json, Value};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Implementation-defined error used by MCP for requests that timed out.
+pub const REQUEST_TIMEOUT: i64 = -32001;
+/// Server error returned for requests received while the runner shuts down.
+pub const SHUTTING_DOWN: i64 = -32002;
+/// Server error returned for messages addressed to a session that no longer exists.
+pub const SESSION_NOT_FOUND: i64 = -32003;
+
+/// A client frame checked against JSON-RPC 2.0.
+#[derive(Debug, Default)]
+pub struct Frame {
+    /// The valid message, or the valid part of a batch, to forward.
+    pub message: Option<Value>,
+    /// Error responses for everything that was rejected.
+    pub errors: Vec<Value>,
+    /// Whether the frame was a batch, which is answered with a single array.
+    pub batch: bool,
+}
+
+impl Frame {
+    /// A frame that could not be parsed at all.
+    pub fn parse_error(reason: impl std::fmt::Display) -> Self {
+        Self {
+            message: None,
+            errors: vec![error_response(Value::Null, PARSE_ERROR, &format!("Parse error: {}", reason))],
+            batch: false,
+        }
+    }
+}
+
+/// Parses and validates a frame received from a client.
+///
+/// Invalid members of a batch are answered individually while the rest of
+/// the batch is kept, as the specification requires.
+pub fn parse_frame(text: &str) -> Frame {
+    let message: Value = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => return Frame::parse_error(e),
+    };
+
+    match message {
+        Value::Array(batch) if batch.is_empty() => Frame {
+            message: None,
+            errors: vec![error_response(Value::Null, INVALID_REQUEST, "Invalid Request: empty batch")],
+            batch: false,
+        },
+        Value::Array(batch) => {
+            let mut frame = Frame { batch: true, ..Frame::default() };
+            let mut valid = Vec::new();
+            for message in batch {
+                match validate(&message) {
+                    Ok(()) => valid.push(message),
+                    Err(reason) => frame.errors.push(invalid_request(&message, reason)),
+                }
+            }
+            frame.message = (!valid.is_empty()).then_some(Value::Array(valid));
+            frame
+        }
+        message => match validate(&message) {
+            Ok(()) => Frame { message: Some(message), errors: Vec::new(), batch: false },
+            Err(reason) => Frame { message: None, errors: vec![invalid_request(&message, reason)], batch: false },
+        },
+    }
+}
+
+/// Checks that a single message is a well-formed request, notification or response.
+fn validate(message: &Value) -> Result<(), &'static str> {
+    let Some(object) = message.as_object() else {
+        return Err("message must be an object");
+    };
+    if object.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err("jsonrpc must be \"2.0\"");
+    }
+    if let Some(id) = object.get("id") {
+        if !(id.is_string() || id.is_number() || id.is_null()) {
+            return Err("id must be a string, number or null");
+        }
+    }
+
+    match object.get("method") {
+        Some(method) => {
+            if !method.is_string() {
+                return Err("method must be a string");
+            }
+            if object.get("params").is_some_and(|params| !(params.is_object() || params.is_array())) {
+                return Err("params must be an object or array");
+            }
+        }
+        None => {
+            // Responses to requests the server sent to the client.
+            if !object.contains_key("id") || object.contains_key("result") == object.contains_key("error") {
+                return Err("message must have a method or be a response");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn invalid_request(message: &Value, reason: &str) -> Value {
+    let id = message
+        .get("id")
+        .filter(|id| id.is_string() || id.is_number())
+        .cloned()
+        .unwrap_or(Value::Null);
+    error_response(id, INVALID_REQUEST, &format!("Invalid Request: {}", reason))
+}
+
+/// Returns the method name of a request or notification.
+pub fn method(message: &Value) -> Option<&str> {
+    message.get("method").and_then(Value::as_str)
+}
+
+/// Returns true if the message is a request (has both a method and an id).
+pub fn is_request(message: &Value) -> bool {
+    method(message).is_some() && message.get("id").is_some()
+}
+
+/// Returns true if the message is a response to an earlier request.
+pub fn is_response(message: &Value) -> bool {
+    method(message).is_none()
+        && message.get("id").is_some()
+        && (message.get("result").is_some() || message.get("error").is_some())
+}
+
+/// Builds a successful response.
+pub fn response(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+/// Builds an error response.
+pub fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Builds a notification.
+pub fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// Returns the ids of the requests in a message or batch.
+pub fn request_ids(message: &Value) -> Vec<Value> {
+    match message {
+        Value::Array(batch) => batch.iter().filter(|message| is_request(message)).map(|message| message["id"].clone()).collect(),
+        message if is_request(message) => vec![message["id"].clone()],
+        _ => Vec::new(),
+    }
+}
+
+/// Shapes responses to a frame for the client: a batch is answered with
+/// one array, a single message with a single object.
+pub fn reply(mut responses: Vec<Value>, batch: bool) -> Option<Value> {
+    match responses.len() {
+        0 => None,
+        1 if !batch => Some(responses.remove(0)),
+        _ => Some(Value::Array(responses)),
+    }
+}
+
+/// Answers every request in a message (or batch) with an error.
+/// Returns the responses and notifications left to forward, if any, and the errors.
+pub fn refuse_requests(message: Value, code: i64, reason: &str) -> (Option<Value>, Vec<Value>) {
+    let refuse = |message: &Value| error_response(message["id"].clone(), code, reason);
+    match message {
+        Value::Array(batch) => {
+            let (requests, rest): (Vec<Value>, Vec<Value>) = batch.into_iter().partition(is_request);
+            let rest = (!rest.is_empty()).then_some(Value::Array(rest));
+            (rest, requests.iter().map(refuse).collect())
+        }
+        message if is_request(&message) => (None, vec![refuse(&message)]),
+        message => (Some(message), Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_code(response: &Value) -> i64 {
+        response["error"]["code"].as_i64().unwrap()
+    }
+
+    #[test]
+    fn single_request_is_forwarded() {
+        let frame = parse_frame(r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#);
+        assert_eq!(frame.message.unwrap()["method"], "tools/list");
+        assert!(frame.errors.is_empty());
+        assert!(!frame.batch);
+    }
+
+    #[test]
+    fn unparsable_frame_is_a_parse_error() {
+        let frame = parse_frame(r#"{"jsonrpc":"2.0","id":1,"#);
+        assert!(frame.message.is_none());
+        assert_eq!(frame.errors.len(), 1);
+        assert_eq!(error_code(&frame.errors[0]), PARSE_ERROR);
+        assert_eq!(frame.errors[0]["id"], Value::Null);
+    }
+
+    #[test]
+    fn empty_batch_is_a_single_invalid_request() {
+        let frame = parse_frame("[]");
+        assert!(frame.message.is_none());
+        assert!(!frame.batch);
+        assert_eq!(frame.errors.len(), 1);
+        assert_eq!(error_code(&frame.errors[0]), INVALID_REQUEST);
+    }
+
+    #[test]
+    fn batch_keeps_valid_members() {
+        let frame = parse_frame(
+            r#"[
+                {"jsonrpc":"2.0","id":1,"method":"tools/list"},
+                1,
+                {"jsonrpc":"1.0","id":"a","method":"ping"},
+                {"jsonrpc":"2.0","method":"notifications/initialized"},
+                {"jsonrpc":"2.0","id":{"nested":true},"method":"ping"}
+            ]"#,
+        );
+        assert!(frame.batch);
+        let forwarded = frame.message.unwrap();
+        assert_eq!(forwarded.as_array().unwrap().len(), 2);
+        assert_eq!(forwarded[0]["id"], 1);
+        assert_eq!(forwarded[1]["method"], "notifications/initialized");
+
+        assert_eq!(frame.errors.len(), 3);
+        assert!(frame.errors.iter().all(|error| error_code(error) == INVALID_REQUEST));
+        // The id of an invalid member is echoed back only when it is usable.
+        assert_eq!(frame.errors[0]["id"], Value::Null);
+        assert_eq!(frame.errors[1]["id"], "a");
+        assert_eq!(frame.errors[2]["id"], Value::Null);
+    }
+
+    #[test]
+    fn batch_of_invalid_members_forwards_nothing() {
+        let frame = parse_frame("[1, 2]");
+        assert!(frame.batch);
+        assert!(frame.message.is_none());
+        assert_eq!(frame.errors.len(), 2);
+    }
+
+    #[test]
+    fn validate_rejects_malformed_messages() {
+        let invalid = [
+            r#"{"jsonrpc":"2.0","id":1,"method":5}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":"ping","params":"x"}"#,
+            r#"{"jsonrpc":"2.0","id":1}"#,
+            r#"{"jsonrpc":"2.0","id":1,"result":{},"error":{}}"#,
+            r#"{"id":1,"method":"ping"}"#,
+            r#""ping""#,
+        ];
+        for text in invalid {
+            let frame = parse_frame(text);
+            assert!(frame.message.is_none(), "{} was accepted", text);
+            assert_eq!(error_code(&frame.errors[0]), INVALID_REQUEST);
+        }
+    }
+
+    #[test]
+    fn validate_accepts_client_responses() {
+        let frame = parse_frame(r#"{"jsonrpc":"2.0","id":"s1","result":{}}"#);
+        assert!(frame.message.is_some());
+        assert!(frame.errors.is_empty());
+    }
+
+    #[test]
+    fn request_ids_skips_notifications_and_responses() {
+        let batch = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "a" },
+            { "jsonrpc": "2.0", "method": "b" },
+            { "jsonrpc": "2.0", "id": 2, "result": {} },
+            { "jsonrpc": "2.0", "id": "x", "method": "c" },
+        ]);
+        assert_eq!(request_ids(&batch), vec![json!(1), json!("x")]);
+        assert!(request_ids(&batch[1]).is_empty());
+    }
+
+    #[test]
+    fn reply_shapes_batches_as_arrays() {
+        let error = error_response(json!(1), INVALID_REQUEST, "Invalid Request");
+        assert_eq!(reply(Vec::new(), true), None);
+        assert_eq!(reply(vec![error.clone()], false), Some(error.clone()));
+        assert_eq!(reply(vec![error.clone()], true), Some(json!([error])));
+    }
+
+    #[test]
+    fn refuse_requests_keeps_notifications() {
+        let batch = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "a" },
+            { "jsonrpc": "2.0", "method": "b" },
+        ]);
+        let (rest, errors) = refuse_requests(batch, SHUTTING_DOWN, "Shutting down");
+        assert_eq!(rest.unwrap().as_array().unwrap().len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["id"], 1);
+        assert_eq!(error_code(&errors[0]), SHUTTING_DOWN);
+    }
+}
@@ -1,13 +1,30 @@
+mod admin;
+mod auth;
+mod batch;
 pub mod config;
 mod constants;
+mod http;
+mod jsonrpc;
+mod logging;
+mod metrics;
+mod origin;
+mod policy;
 mod process;
+mod routing;
+mod session;
 mod shutdown;
 mod state;
+mod tls;
 mod websocket;
 
 // Re-export public API
-pub use constants::MESSAGE_BUFFER_SIZE;
+pub use admin::run_admin_server;
+pub use constants::{CONNECTION_CLOSE_TIMEOUT, MESSAGE_BUFFER_SIZE};
+pub use http::run_http_server;
+pub use metrics::{metrics, run_metrics_server, Transport};
 pub use process::ProcessManager;
-pub use shutdown::shutdown_signal;
+pub use session::{Session, SessionHandle, SessionId, SessionInfo, SessionRegistry};
+pub use shutdown::{handle_hangup, shutdown_signal};
 pub use websocket::handle_connection;
-pub use state::{CONNECTED, SHUTDOWN};
\ No newline at end of file
+pub use state::SessionState;
+pub use tls::TlsContext;
@@ -0,0 +1,199 @@
+//! MCP log levels and the mapping of child stderr lines onto them.
+
+use serde_json::{json, Value};
+
+use crate::jsonrpc;
+
+/// Severity of an MCP `notifications/message` log entry (RFC 5424 levels).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+impl LogLevel {
+    /// Parses a level as sent in `logging/setLevel`.
+    pub fn parse(level: &str) -> Option<Self> {
+        Some(match level {
+            "debug" => LogLevel::Debug,
+            "info" => LogLevel::Info,
+            "notice" => LogLevel::Notice,
+            "warning" => LogLevel::Warning,
+            "error" => LogLevel::Error,
+            "critical" => LogLevel::Critical,
+            "alert" => LogLevel::Alert,
+            "emergency" => LogLevel::Emergency,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+            LogLevel::Error => "error",
+            LogLevel::Critical => "critical",
+            LogLevel::Alert => "alert",
+            LogLevel::Emergency => "emergency",
+        }
+    }
+
+    /// Guesses the level of a stderr line.
+    ///
+    /// A JSON log record's `level` (or `severity`) field wins, then a level
+    /// named at the start of the line (`ERROR:`, `[warn]`, `level=debug`),
+    /// then words found anywhere in the line.
+    pub fn classify(line: &str) -> Self {
+        json_level(line)
+            .or_else(|| prefix_level(line))
+            .unwrap_or_else(|| keyword_level(line))
+    }
+
+    /// Parses a level name as written by common logging libraries.
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "trace" | "debug" | "dbg" => LogLevel::Debug,
+            "info" | "information" => LogLevel::Info,
+            "notice" => LogLevel::Notice,
+            "warn" | "warning" => LogLevel::Warning,
+            "err" | "error" => LogLevel::Error,
+            "crit" | "critical" | "fatal" | "panic" => LogLevel::Critical,
+            "alert" => LogLevel::Alert,
+            "emerg" | "emergency" => LogLevel::Emergency,
+            _ => return None,
+        })
+    }
+}
+
+/// Number of leading words searched for a level name.
+const PREFIX_WORDS: usize = 2;
+
+/// Reads the level of a JSON log record, either a name or a pino/bunyan number.
+fn json_level(line: &str) -> Option<LogLevel> {
+    let trimmed = line.trim();
+    if !trimmed.starts_with('{') {
+        return None;
+    }
+    let record: Value = serde_json::from_str(trimmed).ok()?;
+    let level = ["level", "severity", "lvl"].iter().find_map(|key| record.get(key))?;
+    match level {
+        Value::String(name) => LogLevel::from_name(name),
+        Value::Number(number) => Some(match number.as_u64()? {
+            0..=29 => LogLevel::Debug,
+            30..=39 => LogLevel::Info,
+            40..=49 => LogLevel::Warning,
+            50..=59 => LogLevel::Error,
+            _ => LogLevel::Critical,
+        }),
+        _ => None,
+    }
+}
+
+/// Finds a level named by one of the first words of the line, skipping
+/// timestamps and punctuation such as `[`, `]` and `:`.
+fn prefix_level(line: &str) -> Option<LogLevel> {
+    line.split(|c: char| c.is_whitespace() || "[]()<>|:,".contains(c))
+        .filter(|word| word.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .take(PREFIX_WORDS)
+        .find_map(|word| {
+            let name = ["level=", "lvl=", "severity="]
+                .iter()
+                .find_map(|key| word.strip_prefix(key))
+                .unwrap_or(word);
+            LogLevel::from_name(name)
+        })
+}
+
+/// Guesses a level from words anywhere in a free-form line.
+fn keyword_level(line: &str) -> LogLevel {
+    let line = line.to_ascii_lowercase();
+    let has = |words: &[&str]| words.iter().any(|word| line.contains(word));
+    if has(&["fatal", "panic", "critical"]) {
+        LogLevel::Critical
+    } else if has(&["error", "err:", "exception", "traceback"]) {
+        LogLevel::Error
+    } else if has(&["warn"]) {
+        LogLevel::Warning
+    } else if has(&["debug", "trace"]) {
+        LogLevel::Debug
+    } else {
+        LogLevel::Info
+    }
+}
+
+/// Builds the `notifications/message` notification for a stderr line.
+pub fn stderr_notification(level: LogLevel, line: &str) -> Value {
+    jsonrpc::notification(
+        "notifications/message",
+        json!({ "level": level.as_str(), "logger": "stderr", "data": line }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_records_use_their_level_field() {
+        assert_eq!(LogLevel::classify(r#"{"level":"warn","msg":"disk almost full"}"#), LogLevel::Warning);
+        assert_eq!(LogLevel::classify(r#"{"severity":"ERROR","message":"failed"}"#), LogLevel::Error);
+        // The level field wins over words in the message.
+        assert_eq!(LogLevel::classify(r#"{"level":"info","msg":"0 errors"}"#), LogLevel::Info);
+        // pino and bunyan write numeric levels.
+        assert_eq!(LogLevel::classify(r#"{"level":20,"msg":"x"}"#), LogLevel::Debug);
+        assert_eq!(LogLevel::classify(r#"{"level":30,"msg":"x"}"#), LogLevel::Info);
+        assert_eq!(LogLevel::classify(r#"{"level":40,"msg":"x"}"#), LogLevel::Warning);
+        assert_eq!(LogLevel::classify(r#"{"level":50,"msg":"x"}"#), LogLevel::Error);
+        assert_eq!(LogLevel::classify(r#"{"level":60,"msg":"x"}"#), LogLevel::Critical);
+    }
+
+    #[test]
+    fn json_without_a_level_falls_back_to_keywords() {
+        assert_eq!(LogLevel::classify(r#"{"msg":"request failed with error"}"#), LogLevel::Error);
+        assert_eq!(LogLevel::classify(r#"{"level":"verbose","msg":"hello"}"#), LogLevel::Info);
+        assert_eq!(LogLevel::classify(r#"{"level": oops"#), LogLevel::Info);
+    }
+
+    #[test]
+    fn level_prefixes_in_any_case() {
+        assert_eq!(LogLevel::classify("ERROR: connection refused"), LogLevel::Error);
+        assert_eq!(LogLevel::classify("[warn] retrying"), LogLevel::Warning);
+        assert_eq!(LogLevel::classify("Debug: loaded 3 tools"), LogLevel::Debug);
+        assert_eq!(LogLevel::classify("INFO: 0 errors, 0 warnings"), LogLevel::Info);
+        assert_eq!(LogLevel::classify("2024-05-01T10:00:00Z NOTICE started"), LogLevel::Notice);
+        assert_eq!(LogLevel::classify("WARNING:root:deprecated option"), LogLevel::Warning);
+        assert_eq!(LogLevel::classify("level=error msg=\"boom\""), LogLevel::Error);
+        assert_eq!(LogLevel::classify("time=12:00 level=info msg=\"retrying after error\""), LogLevel::Info);
+    }
+
+    #[test]
+    fn keywords_anywhere_in_free_form_lines() {
+        assert_eq!(LogLevel::classify("thread 'main' panicked at src/main.rs:3"), LogLevel::Critical);
+        assert_eq!(LogLevel::classify("Traceback (most recent call last):"), LogLevel::Error);
+        assert_eq!(LogLevel::classify("Could not connect: Exception raised"), LogLevel::Error);
+        assert_eq!(LogLevel::classify("Server is deprecated, warnings follow"), LogLevel::Warning);
+    }
+
+    #[test]
+    fn other_lines_are_info() {
+        assert_eq!(LogLevel::classify("Server listening on stdio"), LogLevel::Info);
+        assert_eq!(LogLevel::classify(""), LogLevel::Info);
+    }
+
+    #[test]
+    fn set_level_names_round_trip() {
+        for level in [LogLevel::Debug, LogLevel::Warning, LogLevel::Emergency] {
+            assert_eq!(LogLevel::parse(level.as_str()), Some(level));
+        }
+        assert_eq!(LogLevel::parse("WARNING"), None);
+    }
+}
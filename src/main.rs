@@ -2,13 +2,13 @@ use anyhow::{Result};
 use log::{debug, error, info, warn};
 use std::env;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
 use tokio::net::TcpListener;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::mpsc;
 
 use mcp_server_runner::{
-    config::{self, model::ServerConfig},
-    handle_connection, shutdown_signal, ProcessManager, CONNECTED, MESSAGE_BUFFER_SIZE, SHUTDOWN,
+    config::ConfigStore,
+    handle_connection, handle_hangup, CONNECTION_CLOSE_TIMEOUT, metrics, run_admin_server, run_http_server, run_metrics_server,
+    shutdown_signal, SessionRegistry, TlsContext, Transport,
 };
 
 #[tokio::main]
@@ -20,44 +20,87 @@ async fn main() -> Result<()> {
 
     // コマンドライン引数を解析
     let args: Vec<String> = env::args().collect();
-    let config_path = args.iter().skip(1).next().map(|s| s.as_str());
-
-    // 設定の読み込み
-    let config = config::load_config(config_path)?;
-
-    // デフォルトサーバー設定の取得（所有権を取得してクローン）
-    let default_server = config.default_server.clone()
-        .and_then(|server_name| config.servers.get(&server_name).cloned())
-        .ok_or_else(|| anyhow::anyhow!("No default server configuration found"))?;
+    let config_path = args.get(1).map(|s| s.as_str());
+
+    // 設定の読み込み（ファイルの変更時とSIGHUP受信時に再読み込みする）
+    let store = ConfigStore::load(config_path)?;
+    store.watch();
+    let reload_store = Arc::clone(&store);
+    tokio::spawn(handle_hangup(move || reload_store.reload_or_log()));
+    let config = store.current();
+
+    // TLS設定があれば証明書を読み込む
+    let tls = match &config.tls {
+        Some(tls_config) => {
+            let tls = TlsContext::load(tls_config)?;
+            tls.watch();
+            Some(tls)
+        }
+        None => None,
+    };
 
     let addr = format!("{}:{}", config.host, config.port);
     let listener = TcpListener::bind(&addr).await?;
     info!(
-        "WebSocket server started on {} (Default server: {})",
+        "WebSocket server started on {}://{} (Default server: {})",
+        if tls.is_some() { "wss" } else { "ws" },
         &addr,
-        config.default_server.as_ref().unwrap_or(&"unknown".to_string())
+        config.default_server.as_deref().unwrap_or("none")
     );
 
-    // WebSocketサーバーとプロセス管理の設定 - Arcで共有可能にする
-    let process_manager = Arc::new(Mutex::new(ProcessManager::new()));
-    debug!("Process manager initialized");
+    // セッションレジストリの設定 - 接続ごとに子プロセスを管理する
+    let registry = Arc::new(SessionRegistry::new());
+    debug!("Session registry initialized");
 
     let shutdown_handle = tokio::spawn(shutdown_signal());
     debug!("Shutdown handler initialized");
 
+    // HTTPトランスポートが設定されていれば起動
+    let http_task = match &config.http {
+        Some(http) => {
+            let http_addr = format!("{}:{}", http.host, http.port);
+            let http_listener = TcpListener::bind(&http_addr).await?;
+            info!("HTTP transport started on {} (endpoint: /mcp)", http_addr);
+            Some(tokio::spawn(run_http_server(http_listener, Arc::clone(&store), Arc::clone(&registry))))
+        }
+        None => None,
+    };
+
+    // メトリクスが設定されていれば別ポートで公開
+    if let Some(metrics) = &config.metrics {
+        let metrics_addr = format!("{}:{}", metrics.host, metrics.port);
+        let metrics_listener = TcpListener::bind(&metrics_addr).await?;
+        info!("Metrics endpoint started on http://{}/metrics", metrics_addr);
+        tokio::spawn(run_metrics_server(metrics_listener));
+    }
+
+    // 管理APIが設定されていれば別ポートで公開
+    if let Some(admin) = &config.admin {
+        let admin_addr = format!("{}:{}", admin.host, admin.port);
+        let admin_listener = TcpListener::bind(&admin_addr).await?;
+        info!("Admin API started on http://{}", admin_addr);
+        tokio::spawn(run_admin_server(admin_listener, Arc::clone(&store), Arc::clone(&registry)));
+    }
+
+    // 接続ごとのタスクが送信側を保持し、全て終了すると受信側が閉じる
+    let (done_tx, mut done_rx) = mpsc::channel::<()>(1);
+
     // サーバーループを起動（実際のサーバータスクを作成）
-    let manager_clone = Arc::clone(&process_manager);
-    let server_task = tokio::spawn(async move {
+    let registry_clone = Arc::clone(&registry);
+    let server_done_tx = done_tx.clone();
+    let mut server_task = tokio::spawn(async move {
         run_server(
             listener, 
-            manager_clone,
-            default_server,
+            registry_clone,
+            store,
+            tls,
+            server_done_tx,
         ).await
     });
 
     // シャットダウンハンドラーとサーバーのいずれかが終了したら、全体をシャットダウン
     tokio::select! {
-        result = server_task => {
+        result = &mut server_task => {
             match result {
                 Ok(Ok(())) => debug!("Server loop terminated normally"),
                 Ok(Err(e)) => error!("Server loop terminated with error: {}", e),
@@ -66,8 +109,18 @@ async fn main() -> Result<()> {
         },
         _ = shutdown_handle => {
             info!("Initiating shutdown sequence...");
-            let mut pm = process_manager.lock().await;
-            pm.shutdown().await;
+            // 新しい接続の受け付けを停止
+            server_task.abort();
+            if let Some(http_task) = &http_task {
+                http_task.abort();
+            }
+            // 処理中のリクエストへの応答を待ってから子プロセスを停止
+            registry.shutdown_all().await;
+            // クライアントにCloseフレームが届くまで待つ
+            drop(done_tx);
+            if tokio::time::timeout(CONNECTION_CLOSE_TIMEOUT, done_rx.recv()).await.is_err() {
+                warn!("Some connections did not close in time");
+            }
             info!("Shutdown complete");
         }
     }
@@ -78,23 +131,12 @@ async fn main() -> Result<()> {
 /// WebSocketサーバーのメインループを実行
 async fn run_server(
     listener: TcpListener,
-    process_manager: Arc<Mutex<ProcessManager>>,
-    server_config: ServerConfig,
+    registry: Arc<SessionRegistry>,
+    store: Arc<ConfigStore>,
+    tls: Option<Arc<TlsContext>>,
+    done_tx: mpsc::Sender<()>,
 ) -> Result<()> {
     while let Ok((stream, addr)) = listener.accept().await {
-        if SHUTDOWN.load(Ordering::SeqCst) {
-            info!("Shutdown signal received, stopping server");
-            break;
-        }
-
-        if CONNECTED.load(Ordering::SeqCst) {
-            warn!(
-                "Connection rejected: Already have an active connection from: {}",
-                addr
-            );
-            continue;
-        }
-
         info!("New client connection accepted: {}", addr);
         debug!(
             "Client connection details - Local addr: {:?}, Peer addr: {:?}",
@@ -102,34 +144,32 @@ async fn run_server(
             stream.peer_addr()
         );
 
-        let (ws_tx, ws_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
-        debug!(
-            "Created message channels with buffer size: {}",
-            MESSAGE_BUFFER_SIZE
-        );
-
-        // プロセスを起動
-        let process_tx = {
-            let mut pm = process_manager.lock().await;
-            match pm.start_process(&server_config.command, &server_config.args, &server_config.env, ws_tx.clone()).await {
-                Ok(tx) => {
-                    info!("Successfully started child process: {}", server_config.command);
-                    tx
-                }
-                Err(e) => {
-                    error!(
-                        "Failed to start process: {}. Connection will be rejected",
-                        e
-                    );
-                    continue;
-                }
-            }
-        };
-
         debug!("Spawning connection handler for client: {}", addr);
-        tokio::spawn(handle_connection(stream, process_tx, ws_rx));
+        // 接続時点の設定を使う（再読み込みは既存の接続に影響しない）
+        let config = store.current();
+        let registry = Arc::clone(&registry);
+        let acceptor = tls.as_ref().map(|tls| tls.acceptor());
+        let done_tx = done_tx.clone();
+        tokio::spawn(async move {
+            let _done_tx = done_tx;
+            // TLSが有効な場合はWebSocketハンドシェイクの前にTLSハンドシェイクを行う
+            let result = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(tls_stream) => handle_connection(tls_stream, addr, config, registry.clone()).await,
+                    Err(e) => {
+                        metrics().connection(Transport::WebSocket, false);
+                        Err(anyhow::anyhow!("TLS handshake failed: {}", e))
+                    }
+                },
+                None => handle_connection(stream, addr, config, registry.clone()).await,
+            };
+            if let Err(e) = result {
+                error!("Connection error for client {}: {}", addr, e);
+            }
+            debug!("Active sessions: {}", registry.count().await);
+        });
         info!("Connection handler spawned for client: {}", addr);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,378 @@
+use anyhow::Result;
+use hyper::{Method, StatusCode};
+use log::debug;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+use crate::http::{full, serve, text};
+
+/// Upper bounds, in seconds, of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Methods are chosen by clients; past this many distinct ones the rest
+/// are recorded as `other` to bound the number of series.
+const MAX_METHOD_LABELS: usize = 64;
+
+/// Direction of a message relative to the client.
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    /// From the client to the server.
+    In,
+    /// From the server to the client.
+    Out,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+/// Client transport a connection or message went through.
+#[derive(Debug, Clone, Copy)]
+pub enum Transport {
+    WebSocket,
+    /// MCP Streamable HTTP on `/mcp`.
+    StreamableHttp,
+    /// The legacy HTTP+SSE transport on `/sse` and `/messages`.
+    Sse,
+}
+
+impl Transport {
+    fn as_str(self) -> &'static str {
+        match self {
+            Transport::WebSocket => "websocket",
+            Transport::StreamableHttp => "streamable_http",
+            Transport::Sse => "sse",
+        }
+    }
+}
+
+/// Process-wide counters exposed in the Prometheus text format.
+pub struct Metrics {
+    connections: Counter,
+    active_sessions: AtomicI64,
+    messages: Counter,
+    message_bytes: Counter,
+    request_duration: Histogram,
+    spawns: Counter,
+    spawn_failures: Counter,
+    restarts: Counter,
+    exits: Counter,
+}
+
+/// Returns the metrics shared by every listener and session.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            connections: Counter::new(
+                "mcp_runner_connections_total",
+                "Client connections by transport and handshake result.",
+            ),
+            active_sessions: AtomicI64::new(0),
+            messages: Counter::new(
+                "mcp_runner_messages_total",
+                "Client messages by transport and direction.",
+            ),
+            message_bytes: Counter::new(
+                "mcp_runner_message_bytes_total",
+                "Client message payload bytes by transport and direction.",
+            ),
+            request_duration: Histogram::new(
+                "mcp_runner_request_duration_seconds",
+                "Time from forwarding a JSON-RPC request to the child until its response.",
+            ),
+            spawns: Counter::new(
+                "mcp_runner_process_spawns_total",
+                "Child processes started.",
+            ),
+            spawn_failures: Counter::new(
+                "mcp_runner_process_spawn_failures_total",
+                "Child processes that failed to start.",
+            ),
+            restarts: Counter::new(
+                "mcp_runner_process_restarts_total",
+                "Child processes restarted by their restart policy or on request.",
+            ),
+            exits: Counter::new(
+                "mcp_runner_process_exits_total",
+                "Child process exits by exit code.",
+            ),
+        }
+    }
+
+    /// Records a connection that was accepted or rejected. On the HTTP
+    /// transports a connection is a request that opens a session, or any
+    /// request refused before reaching one.
+    pub fn connection(&self, transport: Transport, accepted: bool) {
+        let result = if accepted { "accepted" } else { "rejected" };
+        self.connections.inc(&[("transport", transport.as_str()), ("result", result)]);
+    }
+
+    pub fn session_opened(&self) {
+        self.active_sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_closed(&self) {
+        self.active_sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn message(&self, transport: Transport, direction: Direction, bytes: usize) {
+        let labels = [("transport", transport.as_str()), ("direction", direction.as_str())];
+        self.messages.inc(&labels);
+        self.message_bytes.add(&labels, bytes as u64);
+    }
+
+    pub fn request_completed(&self, method: &str, elapsed: Duration) {
+        self.request_duration.observe(method, elapsed.as_secs_f64());
+    }
+
+    pub fn process_spawned(&self, command: &str) {
+        self.spawns.inc(&[("command", command)]);
+    }
+
+    pub fn spawn_failed(&self, command: &str) {
+        self.spawn_failures.inc(&[("command", command)]);
+    }
+
+    pub fn process_restarted(&self, command: &str) {
+        self.restarts.inc(&[("command", command)]);
+    }
+
+    /// Records a child exit; processes killed by a signal are labelled `signal`.
+    pub fn process_exited(&self, command: &str, status: &ExitStatus) {
+        let code = status.code().map_or_else(|| "signal".to_string(), |code| code.to_string());
+        self.exits.inc(&[("command", command), ("code", &code)]);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.connections.render(&mut out);
+        let _ = writeln!(out, "# HELP mcp_runner_active_sessions Sessions currently open.");
+        let _ = writeln!(out, "# TYPE mcp_runner_active_sessions gauge");
+        let _ = writeln!(
+            out,
+            "mcp_runner_active_sessions {}",
+            self.active_sessions.load(Ordering::Relaxed)
+        );
+        self.messages.render(&mut out);
+        self.message_bytes.render(&mut out);
+        self.request_duration.render(&mut out);
+        self.spawns.render(&mut out);
+        self.spawn_failures.render(&mut out);
+        self.restarts.render(&mut out);
+        self.exits.render(&mut out);
+        out
+    }
+}
+
+/// Serves `GET /metrics` on its own listener until the listener fails.
+pub async fn run_metrics_server(listener: TcpListener) -> Result<()> {
+    serve(listener, |request, addr| async move {
+        debug!("Metrics {} {} from {}", request.method(), request.uri().path(), addr);
+        if request.uri().path() != "/metrics" {
+            return text(StatusCode::NOT_FOUND, "Not found");
+        }
+        if request.method() != Method::GET {
+            return text(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed");
+        }
+        full(StatusCode::OK, "text/plain; version=0.0.4; charset=utf-8", metrics().render())
+    })
+    .await
+}
+
+/// A counter family keyed by its rendered label set.
+struct Counter {
+    name: &'static str,
+    help: &'static str,
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Counter {
+    fn new(name: &'static str, help: &'static str) -> Self {
+        Self { name, help, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    fn inc(&self, labels: &[(&str, &str)]) {
+        self.add(labels, 1);
+    }
+
+    fn add(&self, labels: &[(&str, &str)], value: u64) {
+        *self.values.lock().unwrap().entry(render_labels(labels)).or_default() += value;
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+        for (labels, value) in self.values.lock().unwrap().iter() {
+            let _ = writeln!(out, "{}{} {}", self.name, labels, value);
+        }
+    }
+}
+
+/// A latency histogram family labelled by JSON-RPC method.
+struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    values: Mutex<BTreeMap<String, Buckets>>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    counts: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(name: &'static str, help: &'static str) -> Self {
+        Self { name, help, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    fn observe(&self, method: &str, seconds: f64) {
+        let mut values = self.values.lock().unwrap();
+        let method = if values.contains_key(method) || values.len() < MAX_METHOD_LABELS {
+            method
+        } else {
+            "other"
+        };
+        let buckets = values.entry(method.to_string()).or_default();
+        for (count, bound) in buckets.counts.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *count += 1;
+            }
+        }
+        buckets.count += 1;
+        buckets.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+        for (method, buckets) in self.values.lock().unwrap().iter() {
+            for (count, bound) in buckets.counts.iter().zip(LATENCY_BUCKETS) {
+                let labels = render_labels(&[("method", method), ("le", &bound.to_string())]);
+                let _ = writeln!(out, "{}_bucket{} {}", self.name, labels, count);
+            }
+            let labels = render_labels(&[("method", method), ("le", "+Inf")]);
+            let _ = writeln!(out, "{}_bucket{} {}", self.name, labels, buckets.count);
+            let labels = render_labels(&[("method", method)]);
+            let _ = writeln!(out, "{}_sum{} {}", self.name, labels, buckets.sum);
+            let _ = writeln!(out, "{}_count{} {}", self.name, labels, buckets.count);
+        }
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(render_labels(&[]), "{}");
+        assert_eq!(
+            render_labels(&[("command", "C:\\bin\\server \"x\"\nnext"), ("code", "1")]),
+            r#"{command="C:\\bin\\server \"x\"\nnext",code="1"}"#
+        );
+    }
+
+    #[test]
+    fn counters_render_each_label_set() {
+        let counter = Counter::new("test_total", "Test counter.");
+        counter.inc(&[("direction", "in")]);
+        counter.add(&[("direction", "in")], 2);
+        counter.inc(&[("direction", "out")]);
+
+        let mut out = String::new();
+        counter.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_total Test counter.\n\
+             # TYPE test_total counter\n\
+             test_total{direction=\"in\"} 3\n\
+             test_total{direction=\"out\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new("test_seconds", "Test histogram.");
+        histogram.observe("tools/call", 0.003);
+        histogram.observe("tools/call", 0.2);
+        histogram.observe("tools/call", 60.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "# HELP test_seconds Test histogram.");
+        assert_eq!(lines[1], "# TYPE test_seconds histogram");
+        assert_eq!(lines[2], r#"test_seconds_bucket{method="tools/call",le="0.005"} 1"#);
+        assert_eq!(lines[6], r#"test_seconds_bucket{method="tools/call",le="0.1"} 1"#);
+        assert_eq!(lines[7], r#"test_seconds_bucket{method="tools/call",le="0.25"} 2"#);
+        assert_eq!(lines[9], r#"test_seconds_bucket{method="tools/call",le="1"} 2"#);
+        assert_eq!(lines[13], r#"test_seconds_bucket{method="tools/call",le="30"} 2"#);
+        assert_eq!(lines[14], r#"test_seconds_bucket{method="tools/call",le="+Inf"} 3"#);
+        assert_eq!(lines[15], r#"test_seconds_sum{method="tools/call"} 60.203"#);
+        assert_eq!(lines[16], r#"test_seconds_count{method="tools/call"} 3"#);
+        assert_eq!(lines.len(), 17);
+    }
+
+    #[test]
+    fn methods_past_the_label_limit_are_other() {
+        let histogram = Histogram::new("test_seconds", "Test histogram.");
+        for index in 0..MAX_METHOD_LABELS {
+            histogram.observe(&format!("method/{}", index), 0.001);
+        }
+        histogram.observe("method/0", 0.001);
+        histogram.observe("one/too/many", 0.001);
+        histogram.observe("another", 0.001);
+
+        let values = histogram.values.lock().unwrap();
+        assert_eq!(values.len(), MAX_METHOD_LABELS + 1);
+        assert_eq!(values["method/0"].count, 2);
+        assert_eq!(values["other"].count, 2);
+        assert!(!values.contains_key("one/too/many"));
+    }
+
+    #[test]
+    fn render_includes_every_family() {
+        let metrics = Metrics::new();
+        metrics.connection(Transport::StreamableHttp, false);
+        metrics.session_opened();
+        metrics.message(Transport::Sse, Direction::Out, 42);
+
+        let out = metrics.render();
+        assert!(out.contains("mcp_runner_connections_total{transport=\"streamable_http\",result=\"rejected\"} 1\n"));
+        assert!(out.contains("# TYPE mcp_runner_active_sessions gauge\nmcp_runner_active_sessions 1\n"));
+        assert!(out.contains("mcp_runner_messages_total{transport=\"sse\",direction=\"out\"} 1\n"));
+        assert!(out.contains("mcp_runner_message_bytes_total{transport=\"sse\",direction=\"out\"} 42\n"));
+        for family in ["mcp_runner_request_duration_seconds", "mcp_runner_process_exits_total"] {
+            assert!(out.contains(&format!("# TYPE {} ", family)), "{} missing", family);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use log::{debug, warn};
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::config::model::Config;
+
+/// Origins allowed by default when listening on a loopback address.
+/// An entry without a port matches the same origin on any port.
+const LOCAL_ORIGINS: &[&str] = &[
+    "http://localhost",
+    "https://localhost",
+    "http://127.0.0.1",
+    "https://127.0.0.1",
+    "http://[::1]",
+    "https://[::1]",
+];
+
+/// Host names allowed by default when listening on a loopback address.
+const LOCAL_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]"];
+
+/// Checks the `Origin` and `Host` headers of incoming requests so that a web
+/// page cannot reach the runner through DNS rebinding.
+///
+/// `None` means any value is accepted. Requests without an `Origin` header
+/// come from non-browser clients and are never rejected for it.
+#[derive(Debug, Clone)]
+pub struct OriginPolicy {
+    origins: Option<Vec<String>>,
+    hosts: Option<Vec<String>>,
+}
+
+/// Reason a request was refused by the [`OriginPolicy`].
+#[derive(Debug)]
+pub enum OriginError {
+    Origin(String),
+    Host(String),
+}
+
+impl fmt::Display for OriginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OriginError::Origin(origin) => write!(f, "Origin '{}' is not allowed", origin),
+            OriginError::Host(host) => write!(f, "Host '{}' is not allowed", host),
+        }
+    }
+}
+
+impl std::error::Error for OriginError {}
+
+impl OriginPolicy {
+    /// Builds the policy for a listener bound to `bind_host`.
+    pub fn new(config: &Config, bind_host: &str) -> Self {
+        let local = is_loopback(bind_host);
+        let defaults = |values: &[&str]| local.then(|| values.iter().map(|value| value.to_string()).collect());
+        Self {
+            origins: config.allowed_origins.clone().or_else(|| defaults(LOCAL_ORIGINS)),
+            hosts: config.allowed_hosts.clone().or_else(|| defaults(LOCAL_HOSTS)),
+        }
+    }
+
+    /// Validates the request's `Origin` and `Host` header values.
+    pub fn check(&self, origin: Option<&str>, host: Option<&str>) -> Result<(), OriginError> {
+        if let (Some(allowed), Some(origin)) = (&self.origins, origin) {
+            if !allowed.iter().any(|entry| origin_matches(entry, origin)) {
+                warn!("Rejected request from disallowed origin: {}", origin);
+                return Err(OriginError::Origin(origin.to_string()));
+            }
+        }
+        if let Some(allowed) = &self.hosts {
+            let host = host.unwrap_or_default();
+            if !allowed.iter().any(|entry| host_matches(entry, host)) {
+                warn!("Rejected request for disallowed host: {} (origin: {})", host, origin.unwrap_or("none"));
+                return Err(OriginError::Host(host.to_string()));
+            }
+        }
+        debug!("Origin check passed (origin: {}, host: {})", origin.unwrap_or("none"), host.unwrap_or("none"));
+        Ok(())
+    }
+}
+
+fn is_loopback(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost") || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+fn origin_matches(entry: &str, origin: &str) -> bool {
+    if entry == "*" || entry.eq_ignore_ascii_case(origin) {
+        return true;
+    }
+    // An entry without a port also matches the same origin with one.
+    origin
+        .get(..entry.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(entry))
+        .and_then(|_| origin[entry.len()..].strip_prefix(':'))
+        .is_some_and(|port| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn host_matches(entry: &str, host: &str) -> bool {
+    entry == "*" || entry.eq_ignore_ascii_case(host) || entry.eq_ignore_ascii_case(strip_port(host))
+}
+
+/// Removes the `:port` suffix of a `Host` header value, keeping IPv6 brackets.
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(index) if !host[index..].contains(']') => &host[..index],
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local() -> OriginPolicy {
+        let defaults = |values: &[&str]| Some(values.iter().map(|value| value.to_string()).collect());
+        OriginPolicy { origins: defaults(LOCAL_ORIGINS), hosts: defaults(LOCAL_HOSTS) }
+    }
+
+    #[test]
+    fn origin_entry_without_port_matches_any_port() {
+        assert!(origin_matches("http://localhost", "http://localhost"));
+        assert!(origin_matches("http://localhost", "http://localhost:5173"));
+        assert!(origin_matches("http://localhost", "HTTP://LOCALHOST:80"));
+        assert!(origin_matches("http://[::1]", "http://[::1]:8080"));
+    }
+
+    #[test]
+    fn origin_entry_rejects_lookalikes() {
+        assert!(!origin_matches("http://localhost", "http://localhost:"));
+        assert!(!origin_matches("http://localhost", "http://localhost:80a"));
+        assert!(!origin_matches("http://localhost", "http://localhost.evil.com"));
+        assert!(!origin_matches("http://localhost", "http://localhost:80@evil.com"));
+        assert!(!origin_matches("http://localhost", "https://localhost:443"));
+        assert!(!origin_matches("http://localhost:3000", "http://localhost:4000"));
+        assert!(!origin_matches("http://localhost", "http://local"));
+    }
+
+    #[test]
+    fn wildcard_entries_match_everything() {
+        assert!(origin_matches("*", "https://example.com:8443"));
+        assert!(host_matches("*", "example.com"));
+    }
+
+    #[test]
+    fn host_matches_with_and_without_port() {
+        assert!(host_matches("localhost", "localhost"));
+        assert!(host_matches("localhost", "LocalHost:8080"));
+        assert!(host_matches("[::1]", "[::1]:8080"));
+        assert!(host_matches("[::1]", "[::1]"));
+        assert!(!host_matches("localhost", "localhost.evil.com"));
+        assert!(!host_matches("127.0.0.1", "127.0.0.2:8080"));
+    }
+
+    #[test]
+    fn strip_port_keeps_ipv6_brackets() {
+        assert_eq!(strip_port("example.com:8080"), "example.com");
+        assert_eq!(strip_port("example.com"), "example.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn loopback_bind_hosts() {
+        assert!(is_loopback("localhost"));
+        assert!(is_loopback("127.0.0.1"));
+        assert!(is_loopback("127.1.2.3"));
+        assert!(is_loopback("::1"));
+        assert!(is_loopback("[::1]"));
+        assert!(!is_loopback("0.0.0.0"));
+        assert!(!is_loopback("192.168.1.10"));
+    }
+
+    #[test]
+    fn check_allows_clients_without_origin() {
+        let policy = local();
+        assert!(policy.check(None, Some("localhost:8080")).is_ok());
+        assert!(policy.check(Some("http://127.0.0.1:8080"), Some("127.0.0.1:8080")).is_ok());
+    }
+
+    #[test]
+    fn check_rejects_rebinding() {
+        let policy = local();
+        assert!(matches!(
+            policy.check(Some("http://evil.com"), Some("localhost:8080")),
+            Err(OriginError::Origin(_))
+        ));
+        assert!(matches!(
+            policy.check(Some("http://localhost:8080"), Some("evil.com:8080")),
+            Err(OriginError::Host(_))
+        ));
+        assert!(matches!(policy.check(None, None), Err(OriginError::Host(_))));
+    }
+
+    #[test]
+    fn unrestricted_policy_accepts_anything() {
+        let policy = OriginPolicy { origins: None, hosts: None };
+        assert!(policy.check(Some("http://evil.com"), None).is_ok());
+    }
+}
@@ -0,0 +1,254 @@
+//! Enforcement of the per-server tool allow/deny policy.
+
+use log::{debug, warn};
+use serde_json::Value;
+
+use crate::config::model::ToolPolicy;
+use crate::jsonrpc;
+
+/// A client message split into what may reach the child and what was refused.
+#[derive(Debug, Default)]
+pub struct Screened {
+    /// The message (or the remaining part of a batch) to forward, if any.
+    pub forward: Option<Value>,
+    /// Error responses for the refused `tools/call` requests.
+    pub rejected: Vec<Value>,
+}
+
+impl ToolPolicy {
+    /// Returns true if the policy doesn't hide any tool.
+    pub fn is_unrestricted(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Returns true if the tool may be listed and called.
+    pub fn allows(&self, tool: &str) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|pattern| glob_match(pattern, tool));
+        allowed && !self.deny.iter().any(|pattern| glob_match(pattern, tool))
+    }
+
+    /// Removes disallowed `tools/call` requests from a client message.
+    pub fn screen(&self, message: Value) -> Screened {
+        if self.is_unrestricted() {
+            return Screened { forward: Some(message), rejected: Vec::new() };
+        }
+
+        match message {
+            Value::Array(batch) => {
+                let mut screened = Screened::default();
+                let mut forward = Vec::new();
+                for message in batch {
+                    if self.refuses(&message) {
+                        screened.rejected.extend(refusal(&message));
+                    } else {
+                        forward.push(message);
+                    }
+                }
+                screened.forward = (!forward.is_empty()).then_some(Value::Array(forward));
+                screened
+            }
+            message if self.refuses(&message) => Screened {
+                forward: None,
+                rejected: refusal(&message).into_iter().collect(),
+            },
+            message => Screened { forward: Some(message), rejected: Vec::new() },
+        }
+    }
+
+    /// Drops hidden tools from a `tools/list` response line written by the child.
+    /// Any other line is returned unchanged.
+    pub fn filter_output(&self, line: String) -> String {
+        if self.is_unrestricted() || !line.contains("\"tools\"") {
+            return line;
+        }
+        let Ok(mut message) = serde_json::from_str::<Value>(&line) else {
+            return line;
+        };
+
+        let mut filtered = false;
+        match &mut message {
+            Value::Array(batch) => {
+                for message in batch {
+                    filtered |= self.filter_list(message);
+                }
+            }
+            message => filtered = self.filter_list(message),
+        }
+        if filtered {
+            message.to_string()
+        } else {
+            line
+        }
+    }
+
+    /// Returns true if the message calls a tool this policy hides.
+    fn refuses(&self, message: &Value) -> bool {
+        if jsonrpc::method(message) != Some("tools/call") {
+            return false;
+        }
+        let tool = tool_name(message);
+        if self.allows(tool) {
+            return false;
+        }
+        warn!("Rejected call to disallowed tool: {}", tool);
+        true
+    }
+
+    /// Removes hidden tools from a response carrying a tool list.
+    fn filter_list(&self, message: &mut Value) -> bool {
+        if !jsonrpc::is_response(message) {
+            return false;
+        }
+        let Some(tools) = message
+            .get_mut("result")
+            .and_then(|result| result.get_mut("tools"))
+            .and_then(Value::as_array_mut)
+        else {
+            return false;
+        };
+
+        let before = tools.len();
+        tools.retain(|tool| tool["name"].as_str().is_some_and(|name| self.allows(name)));
+        debug!("Filtered tools/list response: {} of {} tools visible", tools.len(), before);
+        tools.len() != before
+    }
+}
+
+fn tool_name(message: &Value) -> &str {
+    message["params"]["name"].as_str().unwrap_or_default()
+}
+
+/// Builds the error response for a refused call; notifications get none.
+fn refusal(message: &Value) -> Option<Value> {
+    let id = message.get("id").cloned()?;
+    Some(jsonrpc::error_response(
+        id,
+        jsonrpc::INVALID_PARAMS,
+        &format!("Tool '{}' is not allowed", tool_name(message)),
+    ))
+}
+
+/// Matches `text` against a glob pattern where `*` matches any run of
+/// characters and `?` exactly one.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn policy(allow: &[&str], deny: &[&str]) -> ToolPolicy {
+        let patterns = |patterns: &[&str]| patterns.iter().map(|pattern| pattern.to_string()).collect();
+        ToolPolicy { allow: patterns(allow), deny: patterns(deny) }
+    }
+
+    fn call(id: i64, tool: &str) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": "tools/call", "params": { "name": tool } })
+    }
+
+    #[test]
+    fn glob_empty_pattern_matches_only_empty_text() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "read_file"));
+        assert!(!glob_match("read_file", ""));
+    }
+
+    #[test]
+    fn glob_star_matches_any_run() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "read_file"));
+        assert!(glob_match("**", "read_file"));
+        assert!(glob_match("read_*", "read_"));
+        assert!(glob_match("read_*", "read_file"));
+        assert!(glob_match("*_file", "read_file"));
+        assert!(glob_match("*_*_*", "a_b_c"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("read_*", "write_file"));
+        assert!(!glob_match("*_file", "read_files"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+    }
+
+    #[test]
+    fn glob_question_mark_matches_one_character() {
+        assert!(glob_match("get_?", "get_x"));
+        assert!(glob_match("?", "\u{00e9}"));
+        assert!(!glob_match("get_?", "get_"));
+        assert!(!glob_match("get_?", "get_xy"));
+        assert!(glob_match("get_?*", "get_xy"));
+    }
+
+    #[test]
+    fn glob_is_case_sensitive() {
+        assert!(!glob_match("Read_*", "read_file"));
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        let everything_but_delete = policy(&["*"], &["delete_*"]);
+        assert!(everything_but_delete.allows("read_file"));
+        assert!(!everything_but_delete.allows("delete_repo"));
+
+        let read_only = policy(&["read_*"], &[]);
+        assert!(read_only.allows("read_file"));
+        assert!(!read_only.allows("write_file"));
+        assert!(!read_only.allows(""));
+    }
+
+    #[test]
+    fn screen_refuses_calls_in_batches() {
+        let policy = policy(&[], &["delete_*"]);
+        let notification = json!({ "jsonrpc": "2.0", "method": "tools/call", "params": { "name": "delete_repo" } });
+        let screened = policy.screen(json!([call(1, "read_file"), call(2, "delete_repo"), notification]));
+        assert_eq!(screened.forward, Some(json!([call(1, "read_file")])));
+        assert_eq!(screened.rejected.len(), 1);
+        assert_eq!(screened.rejected[0]["id"], 2);
+        assert_eq!(screened.rejected[0]["error"]["code"], jsonrpc::INVALID_PARAMS);
+
+        let screened = policy.screen(call(3, "delete_repo"));
+        assert!(screened.forward.is_none());
+        assert_eq!(screened.rejected[0]["id"], 3);
+    }
+
+    #[test]
+    fn filter_output_hides_tools() {
+        let policy = policy(&[], &["delete_*"]);
+        let line = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": { "tools": [{ "name": "read_file" }, { "name": "delete_repo" }] }
+        })
+        .to_string();
+        let filtered: Value = serde_json::from_str(&policy.filter_output(line)).unwrap();
+        assert_eq!(filtered["result"]["tools"], json!([{ "name": "read_file" }]));
+
+        let unrelated = r#"{"jsonrpc":"2.0","method":"tools","params":{"tools":[{"name":"delete_repo"}]}}"#;
+        assert_eq!(policy.filter_output(unrelated.to_string()), unrelated);
+    }
+}
@@ -0,0 +1,146 @@
+use serde::Serialize;
+use std::fmt;
+use std::process::ExitStatus;
+
+use crate::config::model::LimitsConfig;
+
+/// Why a child process exited, as reported to clients and the admin API.
+///
+/// `SIGXCPU` is attributed to the CPU time limit when one is configured,
+/// since the kernel sends it only for that. A crash while a memory limit is
+/// configured is reported as a possible memory limit: a failed allocation
+/// often ends that way, but so does any other crash. `SIGKILL` is never
+/// attributed to a limit: the OOM killer, the runner's own shutdown
+/// escalation and other processes send it too.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ExitReason {
+    /// The process exited with a status code.
+    Exited { code: i32 },
+    /// The process was killed by a signal.
+    Signaled { signal: i32 },
+    /// The process used up its CPU time limit and was ended by SIGXCPU.
+    CpuLimit { signal: i32 },
+    /// The process crashed while a memory limit was set, possibly because
+    /// an allocation failed at the limit.
+    PossibleMemoryLimit { signal: i32 },
+}
+
+impl ExitReason {
+    pub fn classify(status: &ExitStatus, limits: &LimitsConfig) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return match signal {
+                    libc::SIGXCPU if limits.cpu_secs.is_some() => Self::CpuLimit { signal },
+                    libc::SIGSEGV | libc::SIGABRT | libc::SIGBUS if limits.memory_mb.is_some() => {
+                        Self::PossibleMemoryLimit { signal }
+                    }
+                    signal => Self::Signaled { signal },
+                };
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = limits;
+        Self::Exited { code: status.code().unwrap_or(-1) }
+    }
+
+    /// Returns the exit code if the process exited on its own.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            Self::Exited { code } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exited { code } => write!(f, "exit code {}", code),
+            Self::Signaled { signal } => write!(f, "signal {}", signal),
+            Self::CpuLimit { signal } => write!(f, "CPU time limit exceeded (signal {})", signal),
+            Self::PossibleMemoryLimit { signal } => {
+                write!(f, "signal {} (possibly memory limit exceeded)", signal)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn signaled(signal: i32) -> ExitStatus {
+        ExitStatus::from_raw(signal)
+    }
+
+    fn limits(memory_mb: Option<u64>, cpu_secs: Option<u64>) -> LimitsConfig {
+        LimitsConfig { memory_mb, cpu_secs, ..LimitsConfig::default() }
+    }
+
+    #[test]
+    fn normal_exit_keeps_its_code() {
+        let limits = limits(Some(64), Some(1));
+        assert_eq!(ExitReason::classify(&exited(0), &limits), ExitReason::Exited { code: 0 });
+        assert_eq!(ExitReason::classify(&exited(3), &limits), ExitReason::Exited { code: 3 });
+        assert_eq!(ExitReason::Exited { code: 3 }.code(), Some(3));
+    }
+
+    #[test]
+    fn sigxcpu_is_the_cpu_limit_only_when_set() {
+        assert_eq!(
+            ExitReason::classify(&signaled(libc::SIGXCPU), &limits(None, Some(1))),
+            ExitReason::CpuLimit { signal: libc::SIGXCPU }
+        );
+        assert_eq!(
+            ExitReason::classify(&signaled(libc::SIGXCPU), &limits(None, None)),
+            ExitReason::Signaled { signal: libc::SIGXCPU }
+        );
+    }
+
+    #[test]
+    fn sigkill_is_never_a_limit() {
+        let limits = limits(Some(64), Some(1));
+        assert_eq!(
+            ExitReason::classify(&signaled(libc::SIGKILL), &limits),
+            ExitReason::Signaled { signal: libc::SIGKILL }
+        );
+    }
+
+    #[test]
+    fn crashes_are_only_possibly_the_memory_limit() {
+        for signal in [libc::SIGSEGV, libc::SIGABRT, libc::SIGBUS] {
+            let reason = ExitReason::classify(&signaled(signal), &limits(Some(64), None));
+            assert_eq!(reason, ExitReason::PossibleMemoryLimit { signal });
+            assert_eq!(reason.code(), None);
+            assert_eq!(
+                ExitReason::classify(&signaled(signal), &limits(None, Some(1))),
+                ExitReason::Signaled { signal }
+            );
+        }
+        let reason = ExitReason::classify(&signaled(libc::SIGSEGV), &limits(Some(64), None));
+        assert_eq!(reason.to_string(), format!("signal {} (possibly memory limit exceeded)", libc::SIGSEGV));
+    }
+
+    #[test]
+    fn other_signals_are_reported_as_such() {
+        let limits = limits(Some(64), Some(1));
+        assert_eq!(
+            ExitReason::classify(&signaled(libc::SIGTERM), &limits),
+            ExitReason::Signaled { signal: libc::SIGTERM }
+        );
+    }
+
+    #[test]
+    fn reasons_serialize_with_a_tag() {
+        let reason = serde_json::to_value(ExitReason::PossibleMemoryLimit { signal: 11 }).unwrap();
+        assert_eq!(reason, serde_json::json!({ "reason": "possible_memory_limit", "signal": 11 }));
+    }
+}
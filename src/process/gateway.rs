@@ -0,0 +1,954 @@
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+use super::manager::{ChildStatus, ProcessManager};
+use crate::batch::PendingBatches;
+use crate::config::model::{ServerConfig, ToolPolicy};
+use crate::constants::{GATEWAY_PROTOCOL_VERSION, MESSAGE_BUFFER_SIZE};
+use crate::jsonrpc;
+use crate::state::SessionState;
+
+/// Several configured servers presented to one client as a single MCP server.
+///
+/// The gateway performs the initialize handshake with every member itself
+/// when it starts (restarted members get it replayed) and answers the
+/// client's `initialize` from the cached results. It merges the members'
+/// tool, resource and prompt lists, prefixing names with the member's name
+/// (`github__create_issue`). Calls are routed to the member owning the name
+/// with the prefix removed.
+pub struct Gateway {
+    members: Vec<Member>,
+    client_tx: mpsc::Sender<String>,
+    router: Mutex<Router>,
+}
+
+/// A member server and the child process running it.
+struct Member {
+    name: String,
+    state: Arc<SessionState>,
+    process: Mutex<ProcessManager>,
+    process_tx: mpsc::Sender<String>,
+}
+
+/// Bookkeeping needed to route traffic between the client and the members.
+struct Router {
+    separator: String,
+    names: Vec<String>,
+    tools: Vec<ToolPolicy>,
+    next_id: u64,
+    /// Requests sent to members, keyed by the gateway-assigned id.
+    pending: HashMap<u64, Pending>,
+    /// Requests sent to every member, keyed by fan-out id.
+    fanouts: HashMap<u64, Fanout>,
+    /// Member requests forwarded to the client: gateway id -> (member, original id).
+    server_requests: HashMap<u64, (usize, Value)>,
+    /// Each member's response to the gateway's own initialize request.
+    handshakes: Vec<Option<Value>>,
+    /// Client initialize requests waiting for the handshakes, with their batch.
+    initialize_waiters: Vec<(Value, Option<u64>)>,
+    /// Which member serves a resource URI, learned from `resources/list`.
+    resources: HashMap<String, usize>,
+    /// Fixed prefixes of resource templates and the member serving them.
+    templates: Vec<(String, usize)>,
+    /// Client batches whose replies are gathered into one array.
+    batches: PendingBatches<()>,
+    /// The client batch being routed, if any.
+    batch: Option<u64>,
+    outbox: Vec<Outgoing>,
+}
+
+/// A request awaiting a member's response.
+enum Pending {
+    /// Forwarded to a single member; the response goes back with the original id.
+    Forward { member: usize, client_id: Value, batch: Option<u64> },
+    /// One member's part of a request sent to every member.
+    Part { member: usize, fanout: u64 },
+    /// The gateway's own initialize request.
+    Handshake { member: usize },
+}
+
+/// A client request sent to every member whose results are merged.
+struct Fanout {
+    client_id: Value,
+    batch: Option<u64>,
+    method: String,
+    remaining: usize,
+    responses: Vec<(usize, Value)>,
+}
+
+/// A message queued for delivery once the router lock is released.
+enum Outgoing {
+    Client(Value),
+    Member(usize, Value),
+}
+
+impl Gateway {
+    /// Launches a child for every member and starts routing between them and the client.
+    /// Returns the gateway and the sender for the client's messages.
+    pub async fn start(
+        separator: &str,
+        servers: Vec<(String, ServerConfig)>,
+        client_tx: mpsc::Sender<String>,
+    ) -> Result<(Arc<Self>, mpsc::Sender<String>)> {
+        let mut members = Vec::new();
+        let mut outputs = Vec::new();
+        for (name, server_config) in &servers {
+            let state = Arc::new(SessionState::new());
+            let (stdout_tx, stdout_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+            let mut process = ProcessManager::new();
+            let process_tx = match process.start_process(server_config, stdout_tx, Arc::clone(&state)).await {
+                Ok(process_tx) => process_tx,
+                Err(e) => {
+                    for member in &members {
+                        shutdown_member(member).await;
+                    }
+                    return Err(e.context(format!("Failed to start gateway member '{}'", name)));
+                }
+            };
+            debug!("Started gateway member '{}' (pid: {:?})", name, process.pid());
+            members.push(Member {
+                name: name.clone(),
+                state,
+                process: Mutex::new(process),
+                process_tx,
+            });
+            outputs.push(stdout_rx);
+        }
+
+        let mut router = Router::new(separator, &servers);
+        router.start_handshakes();
+        let handshakes = std::mem::take(&mut router.outbox);
+        let gateway = Arc::new(Self {
+            members,
+            client_tx,
+            router: Mutex::new(router),
+        });
+        info!(
+            "Started gateway with {} member(s): {}",
+            gateway.members.len(),
+            gateway.members.iter().map(|member| member.name.as_str()).collect::<Vec<_>>().join(", ")
+        );
+
+        for (index, stdout_rx) in outputs.into_iter().enumerate() {
+            tokio::spawn(Arc::clone(&gateway).route_output(index, stdout_rx));
+        }
+        gateway.deliver(handshakes).await;
+        let (input_tx, input_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        tokio::spawn(Arc::clone(&gateway).route_input(input_rx));
+
+        Ok((gateway, input_tx))
+    }
+
+    /// Returns the status of every member's child, with recent stderr lines if `stderr` is set.
+    pub async fn children(&self, stderr: bool) -> Vec<ChildStatus> {
+        let mut children = Vec::new();
+        for member in &self.members {
+            let process = member.process.lock().await;
+            children.push(ChildStatus {
+                server: member.name.clone(),
+                pid: process.pid(),
+                last_exit: process.last_exit(),
+                stderr: stderr.then(|| member.state.recent_stderr()),
+            });
+        }
+        children
+    }
+
+    /// Replaces every member's child.
+    /// Returns false if any of them is no longer supervised.
+    pub async fn restart(&self) -> bool {
+        let mut restarted = true;
+        for member in &self.members {
+            restarted &= member.process.lock().await.restart();
+        }
+        restarted
+    }
+
+    /// Lets every member answer pending requests before it is stopped at shutdown.
+    pub fn go_away(&self) {
+        for member in &self.members {
+            member.state.go_away();
+        }
+    }
+
+    /// Stops every member's child process.
+    pub async fn shutdown(&self) {
+        for member in &self.members {
+            shutdown_member(member).await;
+        }
+    }
+
+    /// Routes every message from the client to the member(s) it concerns.
+    async fn route_input(self: Arc<Self>, mut input_rx: mpsc::Receiver<String>) {
+        while let Some(text) = input_rx.recv().await {
+            let outbox = match serde_json::from_str::<Value>(&text) {
+                Ok(message) => {
+                    let mut router = self.router.lock().await;
+                    match message {
+                        Value::Array(batch) => router.client_batch(batch),
+                        message => router.client_message(message),
+                    }
+                    std::mem::take(&mut router.outbox)
+                }
+                Err(e) => {
+                    warn!("Rejecting unparsable message sent to gateway: {}", e);
+                    let error = jsonrpc::error_response(Value::Null, jsonrpc::PARSE_ERROR, "Parse error");
+                    vec![Outgoing::Client(error)]
+                }
+            };
+            self.deliver(outbox).await;
+        }
+        debug!("Gateway input routing finished");
+    }
+
+    /// Routes every line a member writes back to the client.
+    async fn route_output(self: Arc<Self>, member: usize, mut stdout_rx: mpsc::Receiver<String>) {
+        while let Some(text) = stdout_rx.recv().await {
+            let message: Value = match serde_json::from_str(&text) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Dropping unparsable output from gateway member '{}': {}", self.members[member].name, e);
+                    continue;
+                }
+            };
+
+            let outbox = {
+                let mut router = self.router.lock().await;
+                match message {
+                    Value::Array(batch) => batch.into_iter().for_each(|message| router.server_message(member, message)),
+                    message => router.server_message(member, message),
+                }
+                std::mem::take(&mut router.outbox)
+            };
+            self.deliver(outbox).await;
+        }
+
+        info!("Gateway member '{}' stopped producing output", self.members[member].name);
+        let outbox = {
+            let mut router = self.router.lock().await;
+            router.member_closed(member);
+            std::mem::take(&mut router.outbox)
+        };
+        self.deliver(outbox).await;
+    }
+
+    /// Sends queued messages to the client and the members.
+    ///
+    /// Requests that cannot reach their member are failed right away, which
+    /// may queue further messages for the client.
+    async fn deliver(&self, mut outbox: Vec<Outgoing>) {
+        while !outbox.is_empty() {
+            let mut undeliverable = Vec::new();
+            for outgoing in outbox {
+                match outgoing {
+                    Outgoing::Client(message) => {
+                        if let Err(e) = self.client_tx.send(message.to_string()).await {
+                            debug!("Gateway client went away before delivery: {}", e);
+                        }
+                    }
+                    Outgoing::Member(index, message) => {
+                        let member = &self.members[index];
+                        // The member's handshake is replayed if it gets restarted.
+                        member.state.record_client_message(&message);
+                        let request_id = jsonrpc::is_request(&message).then(|| message["id"].as_u64()).flatten();
+                        if let Err(e) = member.process_tx.send(message.to_string()).await {
+                            error!("Error sending to gateway member '{}': {}", member.name, e);
+                            undeliverable.extend(request_id.map(|id| (index, id)));
+                        }
+                    }
+                }
+            }
+            if undeliverable.is_empty() {
+                break;
+            }
+
+            let mut router = self.router.lock().await;
+            for (member, id) in undeliverable {
+                let reason = format!("Server '{}' is not running", router.names[member]);
+                router.fail_pending(id, member, &reason);
+            }
+            outbox = std::mem::take(&mut router.outbox);
+        }
+    }
+}
+
+impl Router {
+    fn new(separator: &str, servers: &[(String, ServerConfig)]) -> Self {
+        Self {
+            separator: separator.to_string(),
+            names: servers.iter().map(|(name, _)| name.clone()).collect(),
+            tools: servers.iter().map(|(_, config)| config.tools.clone()).collect(),
+            next_id: 0,
+            pending: HashMap::new(),
+            fanouts: HashMap::new(),
+            server_requests: HashMap::new(),
+            handshakes: vec![None; servers.len()],
+            initialize_waiters: Vec::new(),
+            resources: HashMap::new(),
+            templates: Vec::new(),
+            batches: PendingBatches::default(),
+            batch: None,
+            outbox: Vec::new(),
+        }
+    }
+
+    /// Sends the gateway's own initialize request to every member.
+    fn start_handshakes(&mut self) {
+        for member in 0..self.names.len() {
+            self.next_id += 1;
+            self.pending.insert(self.next_id, Pending::Handshake { member });
+            self.outbox.push(Outgoing::Member(
+                member,
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": self.next_id,
+                    "method": "initialize",
+                    "params": {
+                        "protocolVersion": GATEWAY_PROTOCOL_VERSION,
+                        "capabilities": {},
+                        "clientInfo": {
+                            "name": env!("CARGO_PKG_NAME"),
+                            "version": env!("CARGO_PKG_VERSION"),
+                        },
+                    },
+                }),
+            ));
+        }
+    }
+
+    /// Caches a member's initialize response and completes its handshake.
+    fn complete_handshake(&mut self, member: usize, response: Value) {
+        if response.get("result").is_some() {
+            debug!("Gateway member '{}' initialized", self.names[member]);
+            let initialized = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+            self.outbox.push(Outgoing::Member(member, initialized));
+        } else {
+            warn!("Gateway member '{}' failed to initialize: {}", self.names[member], response["error"]);
+        }
+        self.handshakes[member] = Some(response);
+        self.answer_initialize();
+    }
+
+    /// Answers the client's initialize requests once every member's handshake is done.
+    fn answer_initialize(&mut self) {
+        if self.initialize_waiters.is_empty() || self.handshakes.iter().any(Option::is_none) {
+            return;
+        }
+        let responses: Vec<(usize, Value)> = self.handshakes.iter().flatten().cloned().enumerate().collect();
+        let reply = self.merge("initialize", responses);
+        for (client_id, batch) in std::mem::take(&mut self.initialize_waiters) {
+            let response = match &reply {
+                Ok(result) => jsonrpc::response(client_id, result.clone()),
+                Err(error) => json!({ "jsonrpc": "2.0", "id": client_id, "error": error }),
+            };
+            self.answer(batch, response);
+        }
+    }
+
+    /// Routes every message of a client batch; the replies go back as one array.
+    fn client_batch(&mut self, messages: Vec<Value>) {
+        let batch = self.batches.open(());
+        self.batch = Some(batch);
+        messages.into_iter().for_each(|message| self.client_message(message));
+        self.batch = None;
+        if let Some(((), reply)) = self.batches.close(batch) {
+            self.outbox.push(Outgoing::Client(reply));
+        }
+    }
+
+    /// Decides where a single message from the client goes.
+    fn client_message(&mut self, mut message: Value) {
+        if jsonrpc::is_response(&message) {
+            // The client answering a request one of the members made.
+            match message["id"].as_u64().and_then(|id| self.server_requests.remove(&id)) {
+                Some((member, original_id)) => {
+                    message["id"] = original_id;
+                    self.outbox.push(Outgoing::Member(member, message));
+                }
+                None => warn!("Dropping client response with unknown id: {}", message["id"]),
+            }
+            return;
+        }
+
+        let method = jsonrpc::method(&message).unwrap_or_default().to_string();
+        if !jsonrpc::is_request(&message) {
+            self.client_notification(&method, message);
+            return;
+        }
+
+        match method.as_str() {
+            "initialize" => {
+                let batch = self.expect();
+                self.initialize_waiters.push((message["id"].take(), batch));
+                self.answer_initialize();
+            }
+            "tools/list" | "resources/list" | "resources/templates/list" | "prompts/list" | "logging/setLevel" => {
+                self.fan_out(method, message)
+            }
+            "ping" => self.reply(message["id"].take(), json!({})),
+            "tools/call" | "prompts/get" => {
+                let Some((member, name)) = self.split_name(message["params"]["name"].as_str()) else {
+                    return self.fail(message, jsonrpc::INVALID_PARAMS, "Unknown tool or prompt name");
+                };
+                if method == "tools/call" && !self.tools[member].allows(&name) {
+                    warn!("Rejected call to disallowed tool: {}", name);
+                    let reason = format!("Tool '{}' is not allowed", message["params"]["name"].as_str().unwrap_or_default());
+                    return self.fail(message, jsonrpc::INVALID_PARAMS, &reason);
+                }
+                message["params"]["name"] = Value::from(name);
+                self.forward(member, message);
+            }
+            "resources/read" | "resources/subscribe" | "resources/unsubscribe" => {
+                match self.resource_owner(message["params"]["uri"].as_str()) {
+                    Some(member) => self.forward(member, message),
+                    // Resources need not be listed; any member may be able to read the URI.
+                    None if method == "resources/read" => self.fan_out(method, message),
+                    None => self.fail(message, jsonrpc::INVALID_PARAMS, "Unknown resource"),
+                }
+            }
+            "completion/complete" => {
+                let reference = &message["params"]["ref"];
+                let target = match reference["type"].as_str() {
+                    Some("ref/prompt") => self.split_name(reference["name"].as_str()),
+                    Some("ref/resource") => self
+                        .resource_owner(reference["uri"].as_str())
+                        .map(|member| (member, String::new())),
+                    _ => None,
+                };
+                match target {
+                    Some((member, name)) => {
+                        if !name.is_empty() {
+                            message["params"]["ref"]["name"] = Value::from(name);
+                        }
+                        self.forward(member, message);
+                    }
+                    None => self.fail(message, jsonrpc::INVALID_PARAMS, "Unknown completion reference"),
+                }
+            }
+            _ => self.fail(message, jsonrpc::METHOD_NOT_FOUND, "Method not found"),
+        }
+    }
+
+    /// Routes a client notification: cancellations go to the member handling
+    /// the request, everything else to every member.
+    fn client_notification(&mut self, method: &str, mut message: Value) {
+        if method == "notifications/initialized" {
+            // The gateway already completed the members' handshakes itself.
+            return;
+        }
+        if method == "notifications/cancelled" {
+            let request_id = &message["params"]["requestId"];
+            let target = self.pending.iter_mut().find_map(|(id, pending)| match pending {
+                Pending::Forward { member, client_id, batch } if client_id == request_id => Some((*member, *id, batch.take())),
+                _ => None,
+            });
+            if let Some((member, id, batch)) = target {
+                message["params"]["requestId"] = Value::from(id);
+                self.outbox.push(Outgoing::Member(member, message));
+                // The client no longer waits for the response; its batch doesn't either.
+                if let Some(((), reply)) = batch.and_then(|batch| self.batches.answer(batch, None)) {
+                    self.outbox.push(Outgoing::Client(reply));
+                }
+            }
+            return;
+        }
+
+        for member in 0..self.names.len() {
+            self.outbox.push(Outgoing::Member(member, message.clone()));
+        }
+    }
+
+    /// Delivers a message from a member to the client.
+    fn server_message(&mut self, member: usize, mut message: Value) {
+        if jsonrpc::is_response(&message) {
+            match message["id"].as_u64().and_then(|id| self.pending.remove(&id)) {
+                Some(Pending::Forward { client_id, batch, .. }) => {
+                    message["id"] = client_id;
+                    self.answer(batch, message);
+                }
+                Some(Pending::Part { fanout, .. }) => self.complete_part(fanout, member, message),
+                Some(Pending::Handshake { .. }) => self.complete_handshake(member, message),
+                None => warn!("Dropping response with unknown id from '{}': {}", self.names[member], message["id"]),
+            }
+            return;
+        }
+
+        if jsonrpc::is_request(&message) {
+            // Server-initiated requests (sampling, roots) are answered by the client.
+            self.next_id += 1;
+            let original_id = std::mem::replace(&mut message["id"], Value::from(self.next_id));
+            self.server_requests.insert(self.next_id, (member, original_id));
+        }
+        self.outbox.push(Outgoing::Client(message));
+    }
+
+    /// Fails everything that was waiting on a member whose output ended.
+    fn member_closed(&mut self, member: usize) {
+        let ids: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| {
+                matches!(
+                    pending,
+                    Pending::Forward { member: m, .. } | Pending::Part { member: m, .. } | Pending::Handshake { member: m }
+                        if *m == member
+                )
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        let reason = format!("Server '{}' exited", self.names[member]);
+        for id in ids {
+            self.fail_pending(id, member, &reason);
+        }
+        self.server_requests.retain(|_, (owner, _)| *owner != member);
+    }
+
+    /// Answers a request sent to a member with an error instead of its response.
+    fn fail_pending(&mut self, id: u64, member: usize, reason: &str) {
+        let mut error = jsonrpc::error_response(Value::from(id), jsonrpc::INTERNAL_ERROR, reason);
+        match self.pending.remove(&id) {
+            Some(Pending::Forward { client_id, batch, .. }) => {
+                error["id"] = client_id;
+                self.answer(batch, error);
+            }
+            Some(Pending::Part { fanout, .. }) => self.complete_part(fanout, member, error),
+            Some(Pending::Handshake { .. }) => self.complete_handshake(member, error),
+            None => {}
+        }
+    }
+
+    /// Sends a request to every member, merging the responses once all have answered.
+    fn fan_out(&mut self, method: String, mut message: Value) {
+        self.next_id += 1;
+        let fanout = self.next_id;
+        let client_id = message["id"].take();
+        // Members are listed in full; their individual cursors can't be merged.
+        if let Some(params) = message.get_mut("params").and_then(Value::as_object_mut) {
+            params.remove("cursor");
+        }
+
+        for member in 0..self.names.len() {
+            self.next_id += 1;
+            let mut request = message.clone();
+            request["id"] = Value::from(self.next_id);
+            self.pending.insert(self.next_id, Pending::Part { member, fanout });
+            self.outbox.push(Outgoing::Member(member, request));
+        }
+        debug!("Fanned out '{}' to {} member(s)", method, self.names.len());
+        let batch = self.expect();
+        self.fanouts.insert(
+            fanout,
+            Fanout { client_id, batch, method, remaining: self.names.len(), responses: Vec::new() },
+        );
+    }
+
+    fn complete_part(&mut self, fanout: u64, member: usize, response: Value) {
+        let Some(state) = self.fanouts.get_mut(&fanout) else {
+            return;
+        };
+        state.responses.push((member, response));
+        state.remaining -= 1;
+        if state.remaining > 0 {
+            return;
+        }
+
+        if let Some(state) = self.fanouts.remove(&fanout) {
+            let reply = match self.merge(&state.method, state.responses) {
+                Ok(result) => jsonrpc::response(state.client_id, result),
+                Err(error) => json!({ "jsonrpc": "2.0", "id": state.client_id, "error": error }),
+            };
+            self.answer(state.batch, reply);
+        }
+    }
+
+    /// Merges the members' results for a fanned-out request; a resource read
+    /// gets the first successful result. Fails only if no member answered successfully.
+    fn merge(&mut self, method: &str, responses: Vec<(usize, Value)>) -> Result<Value, Value> {
+        let mut results = Vec::new();
+        let mut first_error = None;
+        for (member, mut response) in responses {
+            match response.get_mut("result").map(Value::take) {
+                Some(result) => results.push((member, result)),
+                None => {
+                    warn!("Gateway member '{}' failed '{}': {}", self.names[member], method, response["error"]);
+                    first_error.get_or_insert(response["error"].take());
+                }
+            }
+        }
+        if results.is_empty() {
+            return Err(first_error.unwrap_or_else(|| json!({ "code": jsonrpc::INTERNAL_ERROR, "message": "No servers available" })));
+        }
+        results.sort_by_key(|(member, _)| *member);
+
+        Ok(match method {
+            "initialize" => merge_initialize(results),
+            "tools/list" => json!({ "tools": self.merge_list(results, "tools") }),
+            "prompts/list" => json!({ "prompts": self.merge_list(results, "prompts") }),
+            "resources/list" => json!({ "resources": self.merge_list(results, "resources") }),
+            "resources/templates/list" => json!({ "resourceTemplates": self.merge_list(results, "resourceTemplates") }),
+            "resources/read" => results.swap_remove(0).1,
+            _ => json!({}),
+        })
+    }
+
+    /// Concatenates the members' lists, prefixing names and remembering who owns each resource.
+    fn merge_list(&mut self, results: Vec<(usize, Value)>, key: &str) -> Vec<Value> {
+        if key == "resources" {
+            self.resources.clear();
+        } else if key == "resourceTemplates" {
+            self.templates.clear();
+        }
+
+        let mut merged = Vec::new();
+        for (member, mut result) in results {
+            let Some(Value::Array(items)) = result.get_mut(key).map(Value::take) else {
+                continue;
+            };
+            for mut item in items {
+                let name = item["name"].as_str().unwrap_or_default().to_string();
+                if key == "tools" && !self.tools[member].allows(&name) {
+                    continue;
+                }
+                match key {
+                    "resources" => {
+                        if let Some(uri) = item["uri"].as_str() {
+                            self.resources.insert(uri.to_string(), member);
+                        }
+                    }
+                    "resourceTemplates" => {
+                        if let Some(template) = item["uriTemplate"].as_str() {
+                            let prefix = template.split('{').next().unwrap_or_default();
+                            self.templates.push((prefix.to_string(), member));
+                        }
+                    }
+                    _ => {}
+                }
+                item["name"] = Value::from(format!("{}{}{}", self.names[member], self.separator, name));
+                merged.push(item);
+            }
+        }
+        merged
+    }
+
+    /// Splits a prefixed name into its member and the member's own name.
+    fn split_name(&self, name: Option<&str>) -> Option<(usize, String)> {
+        let (server, name) = name?.split_once(&self.separator)?;
+        let member = self.names.iter().position(|member| member == server)?;
+        Some((member, name.to_string()))
+    }
+
+    /// Finds the member serving a resource URI.
+    fn resource_owner(&self, uri: Option<&str>) -> Option<usize> {
+        let uri = uri?;
+        if let Some(member) = self.resources.get(uri) {
+            return Some(*member);
+        }
+        self.templates
+            .iter()
+            .filter(|(prefix, _)| uri.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, member)| *member)
+            .or_else(|| (self.names.len() == 1).then_some(0))
+    }
+
+    fn forward(&mut self, member: usize, mut message: Value) {
+        self.next_id += 1;
+        let id = self.next_id;
+        let client_id = std::mem::replace(&mut message["id"], Value::from(id));
+        let batch = self.expect();
+        self.pending.insert(id, Pending::Forward { member, client_id, batch });
+        self.outbox.push(Outgoing::Member(member, message));
+    }
+
+    fn reply(&mut self, id: Value, result: Value) {
+        self.respond(jsonrpc::response(id, result));
+    }
+
+    fn fail(&mut self, mut message: Value, code: i64, reason: &str) {
+        debug!("Gateway rejected '{}': {}", jsonrpc::method(&message).unwrap_or_default(), reason);
+        let error = jsonrpc::error_response(message["id"].take(), code, reason);
+        self.respond(error);
+    }
+
+    /// Counts a request sent on behalf of the batch being routed; returns that batch.
+    fn expect(&mut self) -> Option<u64> {
+        if let Some(batch) = self.batch {
+            self.batches.expect(batch);
+        }
+        self.batch
+    }
+
+    /// Sends the gateway's own reply, or adds it to the batch being routed.
+    fn respond(&mut self, response: Value) {
+        match self.batch {
+            Some(batch) => self.batches.push(batch, response),
+            None => self.outbox.push(Outgoing::Client(response)),
+        }
+    }
+
+    /// Sends a member's response, or adds it to the batch it answers.
+    fn answer(&mut self, batch: Option<u64>, response: Value) {
+        match batch {
+            Some(batch) => {
+                if let Some(((), reply)) = self.batches.answer(batch, Some(response)) {
+                    self.outbox.push(Outgoing::Client(reply));
+                }
+            }
+            None => self.outbox.push(Outgoing::Client(response)),
+        }
+    }
+}
+
+/// Combines the members' initialize results into the gateway's own.
+fn merge_initialize(results: Vec<(usize, Value)>) -> Value {
+    let protocol_version = results[0].1["protocolVersion"].clone();
+    let mut capabilities = Map::new();
+    for (_, result) in &results {
+        let Some(member_capabilities) = result["capabilities"].as_object() else {
+            continue;
+        };
+        for key in ["tools", "resources", "prompts", "logging", "completions"] {
+            let Some(Value::Object(flags)) = member_capabilities.get(key) else {
+                continue;
+            };
+            let merged = capabilities.entry(key).or_insert_with(|| json!({}));
+            for (flag, value) in flags {
+                if value.as_bool() == Some(true) {
+                    merged[flag] = Value::Bool(true);
+                }
+            }
+        }
+    }
+
+    json!({
+        "protocolVersion": protocol_version,
+        "capabilities": capabilities,
+        "serverInfo": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+    })
+}
+
+async fn shutdown_member(member: &Member) {
+    member.process.lock().await.shutdown().await;
+    member.state.shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(names: &[&str]) -> Router {
+        let servers: Vec<(String, ServerConfig)> =
+            names.iter().map(|name| (name.to_string(), ServerConfig::default())).collect();
+        Router::new("__", &servers)
+    }
+
+    fn request(id: Value, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    /// Takes the queued messages, split into those for the client and those for members.
+    fn sent(router: &mut Router) -> (Vec<Value>, Vec<(usize, Value)>) {
+        let mut client = Vec::new();
+        let mut members = Vec::new();
+        for outgoing in std::mem::take(&mut router.outbox) {
+            match outgoing {
+                Outgoing::Client(message) => client.push(message),
+                Outgoing::Member(member, message) => members.push((member, message)),
+            }
+        }
+        (client, members)
+    }
+
+    #[test]
+    fn batch_replies_come_back_as_one_array() {
+        let mut router = router(&["a", "b"]);
+        router.client_batch(vec![
+            request(json!(1), "tools/call", json!({ "name": "a__echo" })),
+            request(json!(2), "prompts/get", json!({ "name": "b__greet" })),
+            request(json!(3), "tools/call", json!({ "name": "nope" })),
+        ]);
+        let (client, members) = sent(&mut router);
+        assert!(client.is_empty());
+        assert_eq!(members.len(), 2);
+
+        let (member, call) = &members[1];
+        router.server_message(*member, jsonrpc::response(call["id"].clone(), json!({ "messages": [] })));
+        assert!(sent(&mut router).0.is_empty());
+        let (member, call) = &members[0];
+        router.server_message(*member, jsonrpc::response(call["id"].clone(), json!({ "content": [] })));
+
+        let (client, _) = sent(&mut router);
+        assert_eq!(
+            client,
+            vec![json!([
+                jsonrpc::error_response(json!(3), jsonrpc::INVALID_PARAMS, "Unknown tool or prompt name"),
+                jsonrpc::response(json!(2), json!({ "messages": [] })),
+                jsonrpc::response(json!(1), json!({ "content": [] })),
+            ])]
+        );
+    }
+
+    /// Answers every queued request to `member` with `result`.
+    fn answer_member(router: &mut Router, members: &[(usize, Value)], member: usize, result: Value) {
+        for (_, request) in members.iter().filter(|(m, _)| *m == member) {
+            router.server_message(member, jsonrpc::response(request["id"].clone(), result.clone()));
+        }
+    }
+
+    #[test]
+    fn split_name_finds_the_member() {
+        let router = router(&["github", "fs"]);
+        assert_eq!(router.split_name(Some("fs__read__file")), Some((1, "read__file".to_string())));
+        assert_eq!(router.split_name(Some("github__create_issue")), Some((0, "create_issue".to_string())));
+        assert_eq!(router.split_name(Some("other__tool")), None);
+        assert_eq!(router.split_name(Some("create_issue")), None);
+        assert_eq!(router.split_name(None), None);
+    }
+
+    #[test]
+    fn merge_list_prefixes_names_and_applies_the_tool_policy() {
+        let mut router = router(&["a", "b"]);
+        router.tools[1].deny = vec!["secret*".to_string()];
+        let results = vec![
+            (0, json!({ "tools": [{ "name": "echo" }] })),
+            (1, json!({ "tools": [{ "name": "echo" }, { "name": "secret_key" }] })),
+        ];
+        assert_eq!(router.merge_list(results, "tools"), vec![json!({ "name": "a__echo" }), json!({ "name": "b__echo" })]);
+
+        let results = vec![
+            (0, json!({ "resources": [{ "name": "readme", "uri": "file:///readme" }] })),
+            (1, json!({ "resources": [{ "name": "issue", "uri": "gh://issue/1" }] })),
+        ];
+        router.merge_list(results, "resources");
+        let results = vec![(1, json!({ "resourceTemplates": [{ "name": "repo", "uriTemplate": "gh://repo/{name}" }] }))];
+        router.merge_list(results, "resourceTemplates");
+        assert_eq!(router.resources.get("gh://issue/1"), Some(&1));
+        assert_eq!(router.templates, vec![("gh://repo/".to_string(), 1)]);
+    }
+
+    #[test]
+    fn resource_owner_uses_lists_then_templates() {
+        // A single member serves everything.
+        assert_eq!(router(&["a"]).resource_owner(Some("file:///other")), Some(0));
+
+        let mut router = router(&["a", "b"]);
+        router.resources.insert("file:///readme".to_string(), 0);
+        router.templates = vec![("gh://".to_string(), 0), ("gh://repo/".to_string(), 1)];
+        assert_eq!(router.resource_owner(Some("file:///readme")), Some(0));
+        // The longest matching template prefix wins.
+        assert_eq!(router.resource_owner(Some("gh://repo/runner")), Some(1));
+        assert_eq!(router.resource_owner(Some("gh://issue/1")), Some(0));
+        assert_eq!(router.resource_owner(Some("file:///other")), None);
+        assert_eq!(router.resource_owner(None), None);
+    }
+
+    #[test]
+    fn fan_out_merges_every_members_part() {
+        let mut router = router(&["a", "b"]);
+        router.client_message(request(json!("list"), "tools/list", json!({ "cursor": "next" })));
+        let (_, members) = sent(&mut router);
+        assert_eq!(members.len(), 2);
+        // Cursors are dropped; every member lists everything.
+        assert!(members.iter().all(|(_, request)| request["params"].get("cursor").is_none()));
+
+        answer_member(&mut router, &members, 1, json!({ "tools": [{ "name": "two" }] }));
+        assert!(sent(&mut router).0.is_empty());
+        answer_member(&mut router, &members, 0, json!({ "tools": [{ "name": "one" }] }));
+        let (client, _) = sent(&mut router);
+        assert_eq!(
+            client,
+            vec![jsonrpc::response(json!("list"), json!({ "tools": [{ "name": "a__one" }, { "name": "b__two" }] }))]
+        );
+        assert!(router.fanouts.is_empty());
+    }
+
+    #[test]
+    fn fan_out_fails_only_without_any_result() {
+        let mut router = router(&["a", "b"]);
+        router.client_message(request(json!(1), "prompts/list", json!({})));
+        router.outbox.clear();
+        router.member_closed(0);
+        assert!(sent(&mut router).0.is_empty());
+        router.member_closed(1);
+        let (client, _) = sent(&mut router);
+        assert_eq!(client, vec![jsonrpc::error_response(json!(1), jsonrpc::INTERNAL_ERROR, "Server 'a' exited")]);
+    }
+
+    #[test]
+    fn merge_initialize_combines_capabilities() {
+        let merged = merge_initialize(vec![
+            (0, json!({ "protocolVersion": "2025-03-26", "capabilities": { "tools": { "listChanged": true } } })),
+            (1, json!({ "protocolVersion": "2024-11-05", "capabilities": {
+                "resources": { "subscribe": true, "listChanged": false },
+                "experimental": { "x": {} },
+            } })),
+        ]);
+        assert_eq!(merged["protocolVersion"], "2025-03-26");
+        assert_eq!(
+            merged["capabilities"],
+            json!({ "tools": { "listChanged": true }, "resources": { "subscribe": true } })
+        );
+        assert_eq!(merged["serverInfo"]["name"], env!("CARGO_PKG_NAME"));
+    }
+
+    #[test]
+    fn client_initialize_is_answered_from_the_handshakes() {
+        let mut router = router(&["a", "b"]);
+        router.start_handshakes();
+        let (_, handshakes) = sent(&mut router);
+        assert_eq!(handshakes.len(), 2);
+        assert!(handshakes.iter().all(|(_, request)| request["params"]["protocolVersion"] == GATEWAY_PROTOCOL_VERSION));
+
+        // The client's initialize waits for every member.
+        router.client_message(request(json!(1), "initialize", json!({})));
+        answer_member(&mut router, &handshakes, 0, json!({ "protocolVersion": "2025-03-26", "capabilities": {} }));
+        let (client, members) = sent(&mut router);
+        assert!(client.is_empty());
+        assert_eq!(members, vec![(0, json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))]);
+
+        router.member_closed(1);
+        let (client, members) = sent(&mut router);
+        assert!(members.is_empty());
+        assert_eq!(client.len(), 1);
+        assert_eq!(client[0]["id"], 1);
+        assert_eq!(client[0]["result"]["protocolVersion"], "2025-03-26");
+
+        // Later initialize requests are answered at once; the client's
+        // initialized notification is not forwarded.
+        router.client_message(request(json!(2), "initialize", json!({})));
+        router.client_message(json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }));
+        let (client, members) = sent(&mut router);
+        assert_eq!(client[0]["id"], 2);
+        assert!(members.is_empty());
+    }
+
+    #[test]
+    fn unknown_resource_reads_ask_every_member() {
+        let mut router = router(&["a", "b"]);
+        router.client_message(request(json!(1), "resources/read", json!({ "uri": "mem://note" })));
+        let (_, members) = sent(&mut router);
+        assert_eq!(members.len(), 2);
+
+        let (_, read) = &members[0];
+        router.server_message(0, jsonrpc::error_response(read["id"].clone(), jsonrpc::INVALID_PARAMS, "Unknown resource"));
+        answer_member(&mut router, &members, 1, json!({ "contents": [{ "uri": "mem://note", "text": "hi" }] }));
+        let (client, _) = sent(&mut router);
+        assert_eq!(
+            client,
+            vec![jsonrpc::response(json!(1), json!({ "contents": [{ "uri": "mem://note", "text": "hi" }] }))]
+        );
+
+        // Subscriptions still need a known owner.
+        router.client_message(request(json!(2), "resources/subscribe", json!({ "uri": "mem://note" })));
+        let (client, _) = sent(&mut router);
+        assert_eq!(client[0]["error"]["message"], "Unknown resource");
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+use tokio::process::Command;
+
+#[cfg(unix)]
+use log::{debug, warn};
+
+#[cfg(unix)]
+use crate::constants::{PROCESS_GROUP_KILL_TIMEOUT, PROCESS_GROUP_POLL_INTERVAL};
+
+/// Starts the child as the leader of a new process group, so everything it
+/// spawns (e.g. the node processes behind `npx`) can be signalled together.
+#[cfg(unix)]
+pub fn isolate(command: &mut Command) {
+    command.process_group(0);
+}
+
+/// Placeholder for non-Unix systems.
+#[cfg(not(unix))]
+pub fn isolate(_command: &mut Command) {}
+
+/// Sends `signal` to every process in the group led by `pgid`.
+/// Returns false if the group has no processes left.
+#[cfg(unix)]
+pub fn signal(pgid: u32, signal: libc::c_int) -> bool {
+    // SAFETY: kill(2) has no memory safety requirements.
+    unsafe { libc::kill(-(pgid as libc::pid_t), signal) == 0 }
+}
+
+/// Stops the processes left in a group after its leader exited: SIGTERM
+/// first, then SIGKILL if they are still running after `timeout`.
+#[cfg(unix)]
+pub async fn terminate(pgid: u32, timeout: Duration) {
+    if !signal(pgid, 0) {
+        return;
+    }
+    debug!("Processes of group {} outlived the child, sending SIGTERM", pgid);
+    signal(pgid, libc::SIGTERM);
+    if wait_empty(pgid, timeout).await {
+        return;
+    }
+
+    warn!("Processes of group {} did not terminate, killing them", pgid);
+    signal(pgid, libc::SIGKILL);
+    // SIGKILL cannot be ignored; what is left are zombies their new parent has not reaped yet.
+    if !wait_empty(pgid, PROCESS_GROUP_KILL_TIMEOUT).await {
+        debug!("Processes of group {} were killed but not reaped yet", pgid);
+    }
+}
+
+/// Placeholder for non-Unix systems, where descendants are not tracked.
+#[cfg(not(unix))]
+pub async fn terminate(_pgid: u32, _timeout: Duration) {}
+
+/// Waits up to `timeout` for the group to have no processes left.
+#[cfg(unix)]
+async fn wait_empty(pgid: u32, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        // When the runner is PID 1 (e.g. in a container), orphaned members are
+        // reparented to it and stay zombies until reaped here. The group's
+        // leader has already been reaped, so no tokio-managed child matches.
+        // SAFETY: waitpid(2) is given a null status pointer, which it accepts.
+        while unsafe { libc::waitpid(-(pgid as libc::pid_t), std::ptr::null_mut(), libc::WNOHANG) } > 0 {}
+        if !signal(pgid, 0) {
+            return true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(PROCESS_GROUP_POLL_INTERVAL).await;
+    }
+}
@@ -1,44 +1,93 @@
 use log::{debug, error, info, warn};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
 use tokio::process::{ChildStdin, ChildStdout, ChildStderr};
-use tokio::sync::mpsc;
-use std::sync::atomic::Ordering;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, Instant};
+use std::sync::Arc;
 
-use crate::state::SHUTDOWN;
+use super::timeout::InFlight;
+use crate::config::model::{StderrConfig, ToolPolicy};
+use crate::constants::{HANDSHAKE_REPLAY_TIMEOUT, REPLAY_REQUEST_ID};
+use crate::logging::{stderr_notification, LogLevel};
+use crate::state::{InitializeHandshake, SessionState};
 
+/// Writes messages from the session to the child's stdin.
+///
+/// Requests are tracked in `inflight`; when one with a timeout expires the
+/// client gets an error response on `websocket_tx` and the child a
+/// `notifications/cancelled` for it.
+///
+/// Returns `Ok(())` once the session stops sending (channel closed or
+/// shutdown requested) and an error if the child can no longer be written to.
+/// The receiver is borrowed so it can be handed to a restarted child.
 pub async fn handle_stdin(
     stdin: ChildStdin,
-    mut process_rx: mpsc::Receiver<String>,
-) {
+    process_rx: &mut mpsc::Receiver<String>,
+    state: &SessionState,
+    inflight: &InFlight,
+    websocket_tx: &mpsc::Sender<String>,
+) -> tokio::io::Result<()> {
     let mut writer = BufWriter::new(stdin);
     debug!("Started stdin handler for child process");
 
-    while let Some(message) = process_rx.recv().await {
-        if SHUTDOWN.load(Ordering::SeqCst) {
+    loop {
+        let deadline = inflight.next_deadline();
+        let message = tokio::select! {
+            message = process_rx.recv() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                for expired in inflight.expire() {
+                    if let Err(e) = websocket_tx.send(expired.error.to_string()).await {
+                        debug!("Could not deliver timeout error: {}", e);
+                    }
+                    write_to_process(&mut writer, &expired.cancellation.to_string()).await?;
+                }
+                continue;
+            }
+        };
+
+        if state.is_shutdown() {
             debug!("Shutdown signal received, stopping stdin handler");
             break;
         }
 
         debug!("Received message to send to process. Length: {}", message.len());
+        if let Ok(parsed) = serde_json::from_str::<Value>(&message) {
+            inflight.track(&parsed);
+        }
         if let Err(e) = write_to_process(&mut writer, &message).await {
             error!("Error in stdin handling: {}. Message was: {}", e, message);
-            break;
+            return Err(e);
         }
         debug!("Successfully wrote message to process");
     }
     info!("Stdin handler finished");
+    Ok(())
 }
 
+/// Forwards the child's stdout lines to the session.
+///
+/// Tools hidden by `tools` are removed from `tools/list` responses and late
+/// responses to requests that timed out are dropped. While a
+/// handshake replay is pending, the child's response to the replayed
+/// initialize request is swallowed and reported through `replay`.
 pub async fn handle_stdout(
     stdout: ChildStdout,
     websocket_tx: mpsc::Sender<String>,
+    state: Arc<SessionState>,
+    tools: ToolPolicy,
+    inflight: Arc<InFlight>,
+    mut replay: Option<oneshot::Sender<Value>>,
 ) {
     let mut reader = BufReader::new(stdout);
     let mut line = String::new();
     debug!("Started stdout handler for child process");
 
     while let Ok(n) = reader.read_line(&mut line).await {
-        if should_stop(n) {
+        if should_stop(n, &state) {
             debug!("Stopping stdout handler: {}", 
                 if n == 0 { "EOF reached" } else { "shutdown requested" });
             break;
@@ -48,7 +97,23 @@ pub async fn handle_stdout(
         debug!("Received from process (stdout) - Length: {}, Content: {}", 
             trimmed.len(), trimmed);
 
-        if let Err(e) = websocket_tx.send(trimmed).await {
+        if replay.is_some() {
+            if let Some(response) = replay_response(&trimmed) {
+                debug!("Swallowing response to replayed initialize request");
+                if let Some(replay) = replay.take() {
+                    let _ = replay.send(response);
+                }
+                line.clear();
+                continue;
+            }
+        }
+
+        let Some(output) = inflight.complete(trimmed) else {
+            line.clear();
+            continue;
+        };
+
+        if let Err(e) = websocket_tx.send(tools.filter_output(output)).await {
             error!("Error sending to WebSocket: {}", e);
             break;
         }
@@ -58,13 +123,23 @@ pub async fn handle_stdout(
     info!("Stdout handler finished");
 }
 
-pub async fn handle_stderr(stderr: ChildStderr) {
+/// Logs the child's stderr lines and keeps the most recent ones in the session state.
+///
+/// With `forward` enabled each line is also sent to the client as a
+/// `notifications/message` log notification, honouring the level the client
+/// chose with `logging/setLevel`.
+pub async fn handle_stderr(
+    stderr: ChildStderr,
+    websocket_tx: mpsc::Sender<String>,
+    state: Arc<SessionState>,
+    config: StderrConfig,
+) {
     let mut reader = BufReader::new(stderr);
     let mut line = String::new();
     debug!("Started stderr handler for child process");
 
     while let Ok(n) = reader.read_line(&mut line).await {
-        if should_stop(n) {
+        if should_stop(n, &state) {
             debug!("Stopping stderr handler: {}", 
                 if n == 0 { "EOF reached" } else { "shutdown requested" });
             break;
@@ -72,13 +147,64 @@ pub async fn handle_stderr(stderr: ChildStderr) {
 
         let trimmed = line.trim();
         warn!("Process stderr: {}", trimmed);
+        state.push_stderr(trimmed, config.buffer_lines);
+
+        let level = LogLevel::classify(trimmed);
+        if config.forward && !trimmed.is_empty() && level >= state.log_level() {
+            let notification = stderr_notification(level, trimmed);
+            if let Err(e) = websocket_tx.send(notification.to_string()).await {
+                debug!("Could not forward stderr line: {}", e);
+            }
+        }
         line.clear();
     }
     info!("Stderr handler finished");
 }
 
-async fn write_to_process(
-    writer: &mut BufWriter<ChildStdin>,
+/// Replays a captured initialize handshake to a freshly started child.
+///
+/// The initialize request is sent with [`REPLAY_REQUEST_ID`]; once the child
+/// answers (or the wait times out) the `notifications/initialized`
+/// notification is sent as well.
+pub async fn replay_handshake(
+    stdin: &mut ChildStdin,
+    handshake: InitializeHandshake,
+    response_rx: oneshot::Receiver<Value>,
+) -> tokio::io::Result<()> {
+    let Some(mut request) = handshake.request else {
+        return Ok(());
+    };
+    request["id"] = Value::from(REPLAY_REQUEST_ID);
+    info!("Replaying initialize handshake to restarted child process");
+    write_to_process(stdin, &request.to_string()).await?;
+
+    match tokio::time::timeout(HANDSHAKE_REPLAY_TIMEOUT, response_rx).await {
+        Ok(Ok(response)) if response.get("error").is_some() => {
+            warn!("Restarted child rejected the replayed initialize request: {}", response["error"]);
+        }
+        Ok(Ok(_)) => debug!("Restarted child accepted the replayed initialize request"),
+        Ok(Err(_)) => warn!("Child output ended before the replayed initialize request was answered"),
+        Err(_) => warn!("Timed out waiting for the replayed initialize request to be answered"),
+    }
+
+    if let Some(initialized) = handshake.initialized {
+        write_to_process(stdin, &initialized.to_string()).await?;
+    }
+    Ok(())
+}
+
+/// Returns the parsed response if the line answers the replayed initialize request.
+fn replay_response(line: &str) -> Option<Value> {
+    if !line.contains(REPLAY_REQUEST_ID) {
+        return None;
+    }
+    serde_json::from_str::<Value>(line)
+        .ok()
+        .filter(|message| message["id"] == REPLAY_REQUEST_ID)
+}
+
+async fn write_to_process<W: AsyncWrite + Unpin>(
+    writer: &mut W,
     message: &str,
 ) -> tokio::io::Result<()> {
     debug!("Writing to process - Length: {}, Content: {}", message.len(), message);
@@ -89,6 +215,6 @@ async fn write_to_process(
     Ok(())
 }
 
-fn should_stop(n: usize) -> bool {
-    n == 0 || SHUTDOWN.load(Ordering::SeqCst)
+fn should_stop(n: usize, state: &SessionState) -> bool {
+    n == 0 || state.is_shutdown()
 }
@@ -1,92 +1,187 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use log::{debug, error};
-use std::collections::HashMap;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::process::{Child, Command};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 
-use super::io::{handle_stdin, handle_stdout, handle_stderr};
+use super::exit::ExitReason;
+use super::group;
+use super::sandbox;
+use super::supervisor::Supervisor;
+use crate::config::model::ServerConfig;
 use crate::constants::MESSAGE_BUFFER_SIZE;
+use crate::metrics::metrics;
+use crate::policy::glob_match;
+use crate::state::SessionState;
 
+/// Owns the child process of a single client session.
+///
+/// The child itself is driven by a [`Supervisor`] task, which wires up its
+/// stdio and restarts it according to the server's restart policy.
 pub struct ProcessManager {
-    child: Option<Child>,
+    supervisor: Option<SupervisorHandle>,
+}
+
+/// A child process as reported by the admin API.
+#[derive(Debug, Serialize)]
+pub struct ChildStatus {
+    /// Name of the server the child runs.
+    pub server: String,
+    /// OS process id, absent while the child is not running.
+    pub pid: Option<u32>,
+    /// Why the previous child exited, if one has.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_exit: Option<ExitReason>,
+    /// The most recent lines the child wrote to stderr, oldest first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<Vec<String>>,
+}
+
+/// What the supervisor reports about the child it drives.
+pub(super) struct ChildReport {
+    /// OS process id of the running child, or 0 while none is running.
+    pub pid: AtomicU32,
+    pub last_exit: Mutex<Option<ExitReason>>,
+}
+
+/// Handle for controlling a running supervisor task.
+struct SupervisorHandle {
+    report: Arc<ChildReport>,
+    stop_tx: oneshot::Sender<()>,
+    restart_tx: mpsc::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Default for ProcessManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ProcessManager {
     pub fn new() -> Self {
-        Self { child: None }
+        Self { supervisor: None }
+    }
+
+    /// Returns the OS process id of the running child, if any.
+    pub fn pid(&self) -> Option<u32> {
+        self.supervisor
+            .as_ref()
+            .map(|supervisor| supervisor.report.pid.load(Ordering::SeqCst))
+            .filter(|pid| *pid != 0)
+    }
+
+    /// Returns why the session's previous child exited, if one has.
+    pub fn last_exit(&self) -> Option<ExitReason> {
+        self.supervisor
+            .as_ref()
+            .and_then(|supervisor| supervisor.report.last_exit.lock().unwrap().clone())
     }
 
     pub async fn start_process(
         &mut self,
-        program: &str,
-        args: &[String],
-        env_vars: &HashMap<String, String>,
+        server_config: &ServerConfig,
         websocket_tx: mpsc::Sender<String>,
+        state: Arc<SessionState>,
     ) -> Result<mpsc::Sender<String>> {
-        let child = self.spawn_process(program, args, env_vars)?;
+        let child = Self::spawn_process(server_config)?;
         let (process_tx, process_rx) = mpsc::channel::<String>(MESSAGE_BUFFER_SIZE);
-        
-        self.setup_io_handlers(child, process_rx, websocket_tx)?;
-        
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let (restart_tx, restart_rx) = mpsc::channel(1);
+        let report = Arc::new(ChildReport {
+            pid: AtomicU32::new(child.id().unwrap_or(0)),
+            last_exit: Mutex::new(None),
+        });
+
+        let supervisor = Supervisor::new(
+            server_config.clone(),
+            process_rx,
+            websocket_tx,
+            state,
+            Arc::clone(&report),
+            stop_rx,
+            restart_rx,
+        );
+        let task = tokio::spawn(supervisor.run(child));
+        self.supervisor = Some(SupervisorHandle { report, stop_tx, restart_tx, task });
+
         Ok(process_tx)
     }
 
-    fn spawn_process(
-        &mut self,
-        program: &str,
-        args: &[String],
-        env_vars: &HashMap<String, String>,
-    ) -> Result<Child> {
+    /// Asks the supervisor to replace the child regardless of the restart policy.
+    /// Returns false if the supervisor is no longer running.
+    pub fn restart(&self) -> bool {
+        self.supervisor.as_ref().is_some_and(|supervisor| match supervisor.restart_tx.try_send(()) {
+            // A restart is already pending.
+            Ok(()) | Err(mpsc::error::TrySendError::Full(())) => true,
+            Err(mpsc::error::TrySendError::Closed(())) => false,
+        })
+    }
+
+    pub(super) fn spawn_process(server_config: &ServerConfig) -> Result<Child> {
+        let program = &server_config.command;
+        let args = &server_config.args;
         let mut command = Command::new(program);
-        
+
         if !args.is_empty() {
             command.args(args);
         }
-        
-        for (key, value) in env_vars {
+
+        inherit_env(&mut command, server_config);
+        for (key, value) in &server_config.env {
             command.env(key, value);
         }
+        group::isolate(&mut command);
+        sandbox::apply(&mut command, server_config).inspect_err(|_| metrics().spawn_failed(program))?;
+
+        // Arguments are not logged; they may contain secrets resolved from the config.
+        debug!("Spawning process: {} ({} argument(s))", program, args.len());
 
-        debug!("Spawning process: {} {:?}", program, args);
-        
         let child = command
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
-            .spawn()?;
+            .spawn()
+            .inspect_err(|_| metrics().spawn_failed(program))?;
 
+        metrics().process_spawned(program);
         Ok(child)
     }
 
-    fn setup_io_handlers(
-        &mut self,
-        mut child: Child,
-        process_rx: mpsc::Receiver<String>,
-        websocket_tx: mpsc::Sender<String>,
-    ) -> Result<()> {
-        let stdin = child.stdin.take().context("Failed to get child stdin")?;
-        let stdout = child.stdout.take().context("Failed to get child stdout")?;
-        let stderr = child.stderr.take().context("Failed to get child stderr")?;
-
-        self.child = Some(child);
-
-        tokio::spawn(handle_stdin(stdin, process_rx));
-        tokio::spawn(handle_stdout(stdout, websocket_tx));
-        tokio::spawn(handle_stderr(stderr));
-
-        Ok(())
-    }
-
     pub async fn shutdown(&mut self) {
-        if let Some(mut child) = self.child.take() {
-            debug!("Stopping child process...");
-            if let Err(e) = child.kill().await {
-                error!("Failed to stop child process: {}", e);
+        if let Some(supervisor) = self.supervisor.take() {
+            // The supervisor also stops the child if the stop channel is dropped.
+            let _ = supervisor.stop_tx.send(());
+            if let Err(e) = supervisor.task.await {
+                error!("Supervisor task failed: {}", e);
             }
-            if let Err(e) = child.wait().await {
-                error!("Error waiting for child process to exit: {}", e);
+        }
+    }
+}
+
+/// Limits which of the runner's environment variables the child inherits.
+/// `env_remove` wins over `env_inherit`; variables set in `env` are applied afterwards.
+fn inherit_env(command: &mut Command, server_config: &ServerConfig) {
+    let matches = |patterns: &[String], name: &str| patterns.iter().any(|pattern| glob_match(pattern, name));
+    if server_config.env_clear {
+        command.env_clear();
+    }
+    for (name, value) in std::env::vars_os() {
+        // Variables with non-UTF-8 names match no pattern.
+        let inherited = match name.to_str() {
+            Some(name) => {
+                (!server_config.env_clear || matches(&server_config.env_inherit, name))
+                    && !matches(&server_config.env_remove, name)
             }
-            debug!("Child process stopped");
+            None => !server_config.env_clear,
+        };
+        if server_config.env_clear && inherited {
+            command.env(&name, value);
+        } else if !server_config.env_clear && !inherited {
+            command.env_remove(&name);
         }
     }
-}
\ No newline at end of file
+}
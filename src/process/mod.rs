@@ -1,4 +1,13 @@
+mod exit;
+mod gateway;
+mod group;
 mod io;
 mod manager;
+mod sandbox;
+mod shared;
+mod supervisor;
+mod timeout;
 
-pub use manager::ProcessManager;
\ No newline at end of file
+pub use gateway::Gateway;
+pub use manager::{ChildStatus, ProcessManager};
+pub use shared::{ClientId, SharedProcess};
@@ -0,0 +1,222 @@
+use anyhow::Result;
+use tokio::process::Command;
+
+use crate::config::model::ServerConfig;
+
+/// Applies the server's resource limits and sandbox to a command about to be spawned.
+///
+/// Everything that can fail is prepared here; between fork and exec the
+/// child only makes the system calls that install what was prepared.
+#[cfg(unix)]
+pub fn apply(command: &mut Command, server_config: &ServerConfig) -> Result<()> {
+    let sandbox = &server_config.sandbox;
+    if let Some(dir) = &sandbox.working_dir {
+        command.current_dir(dir);
+    }
+    if let Some(gid) = sandbox.gid {
+        command.gid(gid);
+    }
+    if let Some(uid) = sandbox.uid {
+        command.uid(uid);
+    }
+
+    let limits = rlimits(&server_config.limits);
+    #[cfg(target_os = "linux")]
+    let ruleset = sandbox.filesystem.as_ref().map(landlock::ruleset).transpose()?;
+    #[cfg(target_os = "linux")]
+    let no_new_privs = sandbox.no_new_privs || ruleset.is_some();
+    #[cfg(not(target_os = "linux"))]
+    let no_new_privs = false;
+    if limits.is_empty() && !no_new_privs {
+        return Ok(());
+    }
+
+    // SAFETY: the closure runs in the forked child after the uid/gid switch
+    // and only makes async-signal-safe system calls on data prepared above.
+    unsafe {
+        command.pre_exec(move || {
+            for (resource, limit) in &limits {
+                if libc::setrlimit(*resource, limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            #[cfg(target_os = "linux")]
+            {
+                if no_new_privs && libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if let Some(ruleset) = &ruleset {
+                    landlock::restrict_self(ruleset)?;
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Placeholder for non-Unix systems; only the working directory applies.
+/// Configs using anything else are rejected when they are loaded.
+#[cfg(not(unix))]
+pub fn apply(command: &mut Command, server_config: &ServerConfig) -> Result<()> {
+    if let Some(dir) = &server_config.sandbox.working_dir {
+        command.current_dir(dir);
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type Resource = libc::c_int;
+
+#[cfg(unix)]
+fn rlimits(limits: &crate::config::model::LimitsConfig) -> Vec<(Resource, libc::rlimit)> {
+    let limit = |soft: u64, hard: u64| libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+    let mut rlimits = Vec::new();
+    if let Some(memory_mb) = limits.memory_mb {
+        let bytes = memory_mb.saturating_mul(1024 * 1024);
+        rlimits.push((libc::RLIMIT_AS, limit(bytes, bytes)));
+    }
+    if let Some(cpu_secs) = limits.cpu_secs {
+        // SIGXCPU at the soft limit gives the process a chance to exit; SIGKILL follows.
+        rlimits.push((libc::RLIMIT_CPU, limit(cpu_secs, cpu_secs.saturating_add(1))));
+    }
+    if let Some(open_files) = limits.open_files {
+        rlimits.push((libc::RLIMIT_NOFILE, limit(open_files, open_files)));
+    }
+    if let Some(processes) = limits.processes {
+        rlimits.push((libc::RLIMIT_NPROC, limit(processes, processes)));
+    }
+    rlimits
+}
+
+/// Filesystem access control with Landlock (Linux 5.13+), through raw system calls.
+#[cfg(target_os = "linux")]
+mod landlock {
+    use anyhow::{Context, Result};
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    use crate::config::model::FilesystemSandbox;
+
+    const CREATE_RULESET_VERSION: libc::c_uint = 1 << 0;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    /// Every right known to the first Landlock ABI.
+    const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    const READ_ACCESS: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+    /// Rights that can be granted on a file rather than a directory.
+    const FILE_ACCESS: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Builds a ruleset that denies all filesystem access outside the configured paths.
+    pub fn ruleset(filesystem: &FilesystemSandbox) -> Result<OwnedFd> {
+        // SAFETY: querying the ABI version takes no attribute.
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0usize,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            return Err(io::Error::last_os_error())
+                .context("The filesystem sandbox requires Landlock, which is not available");
+        }
+
+        let mut handled = ACCESS_FS_V1;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        let attr = RulesetAttr { handled_access_fs: handled };
+        // SAFETY: `attr` outlives the call and its size is passed along.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0 as libc::c_uint,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Failed to create Landlock ruleset");
+        }
+        // SAFETY: the kernel returned a new file descriptor, opened close-on-exec.
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        for path in &filesystem.read_only {
+            add_rule(&ruleset, path, handled & READ_ACCESS)?;
+        }
+        for path in &filesystem.read_write {
+            add_rule(&ruleset, path, handled)?;
+        }
+        Ok(ruleset)
+    }
+
+    fn add_rule(ruleset: &OwnedFd, path: &str, access: u64) -> Result<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+            .open(path)
+            .with_context(|| format!("Failed to open sandbox path '{}'", path))?;
+        let is_dir = file
+            .metadata()
+            .with_context(|| format!("Failed to read metadata of sandbox path '{}'", path))?
+            .is_dir();
+        let attr = PathBeneathAttr {
+            allowed_access: if is_dir { access } else { access & FILE_ACCESS },
+            parent_fd: file.as_raw_fd(),
+        };
+        // SAFETY: `attr` outlives the call and `file` stays open until it returns.
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0 as libc::c_uint,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("Failed to add sandbox path '{}'", path));
+        }
+        Ok(())
+    }
+
+    /// Enforces the ruleset on the calling process; `no_new_privs` must already be set.
+    pub fn restrict_self(ruleset: &OwnedFd) -> io::Result<()> {
+        // SAFETY: landlock_restrict_self only reads the ruleset file descriptor.
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0 as libc::c_uint) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,626 @@
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+use super::manager::{ChildStatus, ProcessManager};
+use crate::batch::PendingBatches;
+use crate::config::model::ServerConfig;
+use crate::constants::MESSAGE_BUFFER_SIZE;
+use crate::jsonrpc;
+use crate::state::SessionState;
+
+/// Identifier of a client attached to a shared process.
+pub type ClientId = u64;
+
+/// A single child process multiplexed between many clients.
+///
+/// Every request a client sends gets its `id` replaced with a runner-unique
+/// id before reaching the child; responses are routed back to the client
+/// that issued the request with the original id restored.
+pub struct SharedProcess {
+    name: String,
+    /// The definition the child was started with.
+    server_config: ServerConfig,
+    /// Set once a reloaded definition replaced this child; it stops with its last client.
+    retired: AtomicBool,
+    state: Arc<SessionState>,
+    process: Mutex<ProcessManager>,
+    process_tx: mpsc::Sender<String>,
+    router: Mutex<Router>,
+    next_client_id: AtomicU64,
+}
+
+/// Bookkeeping needed to route traffic between clients and the child.
+#[derive(Default)]
+struct Router {
+    clients: HashMap<ClientId, mpsc::Sender<String>>,
+    pending: HashMap<u64, PendingRequest>,
+    next_request_id: u64,
+    initialize: InitializeState,
+    initialized_sent: bool,
+    subscriptions: HashMap<String, HashSet<ClientId>>,
+    progress_tokens: HashMap<String, ClientId>,
+    /// Client batches whose replies are gathered into one array.
+    batches: PendingBatches<ClientId>,
+    outbox: Vec<(mpsc::Sender<String>, Value)>,
+}
+
+/// A client request forwarded to the child and awaiting its response.
+struct PendingRequest {
+    client: ClientId,
+    original_id: Value,
+    method: String,
+    progress_token: Option<String>,
+    /// The client batch the request was part of.
+    batch: Option<u64>,
+}
+
+/// The shared child is initialized once; later clients get the cached result.
+#[derive(Default)]
+enum InitializeState {
+    #[default]
+    NotStarted,
+    /// Clients waiting for the result, with their request id and batch.
+    InFlight(Vec<(ClientId, Value, Option<u64>)>),
+    Done(Value),
+}
+
+/// What to do with a single message received from a client.
+enum Action {
+    Forward(Value),
+    Reply(Value),
+    Drop,
+}
+
+impl SharedProcess {
+    /// Launches the child process and starts routing its output.
+    pub async fn start(name: &str, server_config: &ServerConfig) -> Result<Arc<Self>> {
+        let state = Arc::new(SessionState::new());
+        let (stdout_tx, stdout_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        let mut process = ProcessManager::new();
+        let process_tx = process
+            .start_process(server_config, stdout_tx, Arc::clone(&state))
+            .await?;
+        info!("Started shared process for server '{}' (pid: {:?})", name, process.pid());
+
+        let shared = Arc::new(Self {
+            name: name.to_string(),
+            server_config: server_config.clone(),
+            retired: AtomicBool::new(false),
+            state,
+            process: Mutex::new(process),
+            process_tx,
+            router: Mutex::new(Router::default()),
+            next_client_id: AtomicU64::new(0),
+        });
+        tokio::spawn(Arc::clone(&shared).route_output(stdout_rx));
+
+        Ok(shared)
+    }
+
+    /// Returns false once the child has exited or been shut down.
+    pub fn is_running(&self) -> bool {
+        !self.state.is_shutdown()
+    }
+
+    /// Returns true if the child was started from `server_config`.
+    pub fn serves(&self, server_config: &ServerConfig) -> bool {
+        self.server_config == *server_config
+    }
+
+    /// Stops the child once no client is attached anymore.
+    /// Used when a reloaded definition replaces the child for new clients.
+    pub async fn retire(&self) {
+        self.retired.store(true, Ordering::SeqCst);
+        if self.router.lock().await.clients.is_empty() {
+            info!("Stopping retired shared process for server '{}'", self.name);
+            self.shutdown().await;
+        }
+    }
+
+    /// Returns the OS process id of the shared child.
+    pub async fn pid(&self) -> Option<u32> {
+        self.process.lock().await.pid()
+    }
+
+    /// Returns the shared child's status, with its recent stderr lines if `stderr` is set.
+    pub async fn status(&self, stderr: bool) -> ChildStatus {
+        let process = self.process.lock().await;
+        ChildStatus {
+            server: self.name.clone(),
+            pid: process.pid(),
+            last_exit: process.last_exit(),
+            stderr: stderr.then(|| self.state.recent_stderr()),
+        }
+    }
+
+    /// Replaces the shared child; every attached client is affected.
+    /// Returns false if the child is no longer supervised.
+    pub async fn restart(&self) -> bool {
+        self.process.lock().await.restart()
+    }
+
+    /// Attaches a client, returning its id and the sender for its outgoing messages.
+    pub async fn attach(self: &Arc<Self>, websocket_tx: mpsc::Sender<String>) -> (ClientId, mpsc::Sender<String>) {
+        let client = self.next_client_id.fetch_add(1, Ordering::SeqCst) + 1;
+        self.router.lock().await.clients.insert(client, websocket_tx);
+
+        let (client_tx, client_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        tokio::spawn(Arc::clone(self).route_input(client, client_rx));
+        debug!("Client {} attached to shared server '{}'", client, self.name);
+
+        (client, client_tx)
+    }
+
+    /// Detaches a client and forgets everything routed on its behalf.
+    pub async fn detach(&self, client: ClientId) {
+        let mut router = self.router.lock().await;
+        router.clients.remove(&client);
+        router.pending.retain(|_, pending| pending.client != client);
+        router.progress_tokens.retain(|_, owner| *owner != client);
+        router.batches.remove_client(client);
+        for subscribers in router.subscriptions.values_mut() {
+            subscribers.remove(&client);
+        }
+        router.subscriptions.retain(|_, subscribers| !subscribers.is_empty());
+        debug!(
+            "Client {} detached from shared server '{}' ({} client(s) remaining)",
+            client, self.name, router.clients.len()
+        );
+
+        let idle = router.clients.is_empty();
+        drop(router);
+        if idle && self.retired.load(Ordering::SeqCst) {
+            info!("Stopping retired shared process for server '{}'", self.name);
+            self.shutdown().await;
+        }
+    }
+
+    /// Stops the shared child process.
+    pub async fn shutdown(&self) {
+        self.process.lock().await.shutdown().await;
+        self.state.shutdown();
+    }
+
+    /// Lets the child answer pending requests before it is stopped at shutdown.
+    pub fn go_away(&self) {
+        self.state.go_away();
+    }
+
+    /// Forwards messages from one client to the child, rewriting request ids.
+    async fn route_input(self: Arc<Self>, client: ClientId, mut client_rx: mpsc::Receiver<String>) {
+        while let Some(text) = client_rx.recv().await {
+            let message: Value = match serde_json::from_str(&text) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Forwarding unparsable message from client {} unchanged: {}", client, e);
+                    if self.process_tx.send(text).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            let (forward, outbox) = {
+                let mut router = self.router.lock().await;
+                let forward = router.route_client_message(client, message);
+                (forward, std::mem::take(&mut router.outbox))
+            };
+            deliver(outbox).await;
+
+            if let Some(message) = forward {
+                // The shared child's handshake is replayed if it gets restarted.
+                self.state.record_client_message(&message);
+                if let Err(e) = self.process_tx.send(message.to_string()).await {
+                    error!("Error sending to shared process '{}': {}", self.name, e);
+                    break;
+                }
+            }
+        }
+        debug!("Input routing finished for client {}", client);
+    }
+
+    /// Routes every line the child writes to the client(s) it belongs to.
+    async fn route_output(self: Arc<Self>, mut stdout_rx: mpsc::Receiver<String>) {
+        while let Some(text) = stdout_rx.recv().await {
+            let message: Value = match serde_json::from_str(&text) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Dropping unparsable output from shared server '{}': {}", self.name, e);
+                    continue;
+                }
+            };
+
+            let outbox = {
+                let mut router = self.router.lock().await;
+                match message {
+                    Value::Array(batch) => batch.into_iter().for_each(|message| router.server_message(message)),
+                    message => router.server_message(message),
+                }
+                std::mem::take(&mut router.outbox)
+            };
+            deliver(outbox).await;
+        }
+
+        info!("Shared process for server '{}' stopped producing output", self.name);
+        self.state.shutdown();
+        // Dropping the client senders ends every attached session.
+        self.router.lock().await.clients.clear();
+    }
+}
+
+impl Router {
+    /// Rewrites a single message or batch from a client, returning what to send to the child.
+    /// The replies to a batch go back to the client as one array.
+    fn route_client_message(&mut self, client: ClientId, message: Value) -> Option<Value> {
+        let messages = match message {
+            Value::Array(messages) => messages,
+            message => {
+                return match self.client_message(client, message, None) {
+                    Action::Forward(message) => Some(message),
+                    Action::Reply(reply) => {
+                        self.send_to(client, reply);
+                        None
+                    }
+                    Action::Drop => None,
+                };
+            }
+        };
+
+        let batch = self.batches.open(client);
+        let mut forwarded = Vec::new();
+        for message in messages {
+            match self.client_message(client, message, Some(batch)) {
+                Action::Forward(message) => forwarded.push(message),
+                Action::Reply(reply) => self.batches.push(batch, reply),
+                Action::Drop => {}
+            }
+        }
+        if let Some((client, reply)) = self.batches.close(batch) {
+            self.send_to(client, reply);
+        }
+        (!forwarded.is_empty()).then_some(Value::Array(forwarded))
+    }
+
+    /// Decides how a message from a client, possibly part of `batch`, reaches the child.
+    fn client_message(&mut self, client: ClientId, mut message: Value, batch: Option<u64>) -> Action {
+        if jsonrpc::is_request(&message) {
+            let method = jsonrpc::method(&message).unwrap_or_default().to_string();
+            let original_id = message["id"].take();
+
+            match method.as_str() {
+                "initialize" => match &mut self.initialize {
+                    InitializeState::Done(result) => {
+                        return Action::Reply(jsonrpc::response(original_id, result.clone()));
+                    }
+                    InitializeState::InFlight(waiters) => {
+                        waiters.push((client, original_id, batch));
+                        if let Some(batch) = batch {
+                            self.batches.expect(batch);
+                        }
+                        return Action::Drop;
+                    }
+                    InitializeState::NotStarted => {
+                        self.initialize = InitializeState::InFlight(Vec::new());
+                    }
+                },
+                "resources/subscribe" => {
+                    if let Some(uri) = message["params"]["uri"].as_str() {
+                        self.subscriptions.entry(uri.to_string()).or_default().insert(client);
+                    }
+                }
+                "resources/unsubscribe" => {
+                    if let Some(uri) = message["params"]["uri"].as_str() {
+                        if let Some(subscribers) = self.subscriptions.get_mut(uri) {
+                            subscribers.remove(&client);
+                            if !subscribers.is_empty() {
+                                // Other clients still need updates for this resource.
+                                return Action::Reply(jsonrpc::response(original_id, Value::Object(Default::default())));
+                            }
+                            self.subscriptions.remove(uri);
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            let progress_token = message["params"]["_meta"].get("progressToken").map(Value::to_string);
+            if let Some(token) = &progress_token {
+                self.progress_tokens.insert(token.clone(), client);
+            }
+
+            self.next_request_id += 1;
+            let runner_id = self.next_request_id;
+            message["id"] = Value::from(runner_id);
+            debug!("Rewrote request '{}' from client {}: {} -> {}", method, client, original_id, runner_id);
+            self.pending.insert(runner_id, PendingRequest { client, original_id, method, progress_token, batch });
+            if let Some(batch) = batch {
+                self.batches.expect(batch);
+            }
+            return Action::Forward(message);
+        }
+
+        match jsonrpc::method(&message) {
+            Some("notifications/initialized") => {
+                if self.initialized_sent {
+                    return Action::Drop;
+                }
+                self.initialized_sent = true;
+            }
+            Some("notifications/cancelled") => {
+                let request_id = &message["params"]["requestId"];
+                let Some((runner_id, pending)) = self
+                    .pending
+                    .iter_mut()
+                    .find(|(_, pending)| pending.client == client && pending.original_id == *request_id)
+                else {
+                    return Action::Drop;
+                };
+                message["params"]["requestId"] = Value::from(*runner_id);
+                // The client no longer waits for the response; its batch doesn't either.
+                if let Some(batch) = pending.batch.take() {
+                    if let Some((client, reply)) = self.batches.answer(batch, None) {
+                        self.send_to(client, reply);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        // Other notifications and responses to server-initiated requests pass through.
+        Action::Forward(message)
+    }
+
+    /// Delivers a message from the child to the client(s) it concerns.
+    fn server_message(&mut self, mut message: Value) {
+        if jsonrpc::is_response(&message) {
+            let Some(pending) = message["id"].as_u64().and_then(|id| self.pending.remove(&id)) else {
+                warn!("Dropping response with unknown id: {}", message["id"]);
+                return;
+            };
+            if let Some(token) = &pending.progress_token {
+                self.progress_tokens.remove(token);
+            }
+            if pending.method == "initialize" {
+                self.complete_initialize(&message);
+            }
+            message["id"] = pending.original_id;
+            self.reply_to(pending.client, pending.batch, message);
+            return;
+        }
+
+        match jsonrpc::method(&message) {
+            Some("notifications/progress") => {
+                let token = message["params"].get("progressToken").map(Value::to_string);
+                if let Some(client) = token.and_then(|token| self.progress_tokens.get(&token).copied()) {
+                    self.send_to(client, message);
+                }
+            }
+            Some("notifications/resources/updated") => {
+                let subscribers = message["params"]["uri"]
+                    .as_str()
+                    .and_then(|uri| self.subscriptions.get(uri))
+                    .cloned()
+                    .unwrap_or_default();
+                for client in subscribers {
+                    self.send_to(client, message.clone());
+                }
+            }
+            Some(_) if jsonrpc::is_request(&message) => {
+                // Server-initiated requests go to the longest-connected client.
+                if let Some(client) = self.clients.keys().min().copied() {
+                    self.send_to(client, message);
+                }
+            }
+            _ => {
+                for client in self.clients.keys().copied().collect::<Vec<_>>() {
+                    self.send_to(client, message.clone());
+                }
+            }
+        }
+    }
+
+    /// Caches the initialize result and answers clients that were waiting for it.
+    fn complete_initialize(&mut self, response: &Value) {
+        let waiters = match std::mem::take(&mut self.initialize) {
+            InitializeState::InFlight(waiters) => waiters,
+            _ => Vec::new(),
+        };
+        if let Some(result) = response.get("result") {
+            self.initialize = InitializeState::Done(result.clone());
+        }
+        for (client, id, batch) in waiters {
+            let mut reply = response.clone();
+            reply["id"] = id;
+            self.reply_to(client, batch, reply);
+        }
+    }
+
+    /// Sends a response to a client, or adds it to the batch it answers.
+    fn reply_to(&mut self, client: ClientId, batch: Option<u64>, response: Value) {
+        match batch {
+            Some(batch) => {
+                if let Some((client, reply)) = self.batches.answer(batch, Some(response)) {
+                    self.send_to(client, reply);
+                }
+            }
+            None => self.send_to(client, response),
+        }
+    }
+
+    /// Queues a message for a client; it is delivered once the router lock is released.
+    fn send_to(&mut self, client: ClientId, message: Value) {
+        if let Some(tx) = self.clients.get(&client) {
+            self.outbox.push((tx.clone(), message));
+        }
+    }
+}
+
+/// Sends queued messages to their clients.
+async fn deliver(outbox: Vec<(mpsc::Sender<String>, Value)>) {
+    for (tx, message) in outbox {
+        if let Err(e) = tx.send(message.to_string()).await {
+            debug!("Client went away before delivery: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn router(clients: &[ClientId]) -> Router {
+        let mut router = Router::default();
+        for client in clients {
+            let (tx, _) = mpsc::channel(1);
+            router.clients.insert(*client, tx);
+        }
+        router
+    }
+
+    fn request(id: Value, method: &str) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method })
+    }
+
+    /// Takes the messages queued for clients, with the client each one is for.
+    fn sent(router: &mut Router) -> Vec<(ClientId, Value)> {
+        std::mem::take(&mut router.outbox)
+            .into_iter()
+            .map(|(tx, message)| {
+                let client = router.clients.iter().find(|(_, client_tx)| client_tx.same_channel(&tx)).map(|(client, _)| *client);
+                (client.unwrap(), message)
+            })
+            .collect()
+    }
+
+    fn subscribe(method: &str, id: i64, uri: &str) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": { "uri": uri } })
+    }
+
+    #[test]
+    fn batch_replies_come_back_as_one_array() {
+        let mut router = router(&[1]);
+        let forward = router
+            .route_client_message(1, json!([request(json!("a"), "tools/list"), request(json!("b"), "tools/call")]))
+            .unwrap();
+        assert_eq!(forward, json!([request(json!(1), "tools/list"), request(json!(2), "tools/call")]));
+
+        router.server_message(jsonrpc::response(json!(2), json!({ "content": [] })));
+        assert!(sent(&mut router).is_empty());
+        router.server_message(jsonrpc::response(json!(1), json!({ "tools": [] })));
+        assert_eq!(
+            sent(&mut router),
+            vec![(
+                1,
+                json!([
+                    jsonrpc::response(json!("b"), json!({ "content": [] })),
+                    jsonrpc::response(json!("a"), json!({ "tools": [] })),
+                ])
+            )]
+        );
+    }
+
+    #[test]
+    fn request_ids_are_rewritten_and_restored() {
+        let mut router = router(&[1, 2]);
+        // Both clients use the same id; the child sees two distinct ones.
+        let first = router.route_client_message(1, request(json!(7), "tools/call")).unwrap();
+        let second = router.route_client_message(2, request(json!(7), "tools/call")).unwrap();
+        assert_eq!(first["id"], 1);
+        assert_eq!(second["id"], 2);
+
+        router.server_message(jsonrpc::response(json!(2), json!({ "from": "second" })));
+        router.server_message(jsonrpc::response(json!(1), json!({ "from": "first" })));
+        assert_eq!(
+            sent(&mut router),
+            vec![
+                (2, jsonrpc::response(json!(7), json!({ "from": "second" }))),
+                (1, jsonrpc::response(json!(7), json!({ "from": "first" }))),
+            ]
+        );
+
+        // Unknown and already answered ids are dropped.
+        router.server_message(jsonrpc::response(json!(1), json!({})));
+        assert!(sent(&mut router).is_empty());
+        assert!(router.pending.is_empty());
+    }
+
+    #[test]
+    fn initialize_is_forwarded_once_and_cached() {
+        let mut router = router(&[1, 2, 3]);
+        let forward = router.route_client_message(1, request(json!("init"), "initialize"));
+        assert_eq!(forward, Some(request(json!(1), "initialize")));
+        // A client initializing while the child is still answering waits for it.
+        assert_eq!(router.route_client_message(2, request(json!(5), "initialize")), None);
+
+        let result = json!({ "protocolVersion": "2025-03-26", "capabilities": {} });
+        router.server_message(jsonrpc::response(json!(1), result.clone()));
+        assert_eq!(
+            sent(&mut router),
+            vec![
+                (2, jsonrpc::response(json!(5), result.clone())),
+                (1, jsonrpc::response(json!("init"), result.clone())),
+            ]
+        );
+
+        // Later clients get the cached result without reaching the child.
+        assert_eq!(router.route_client_message(3, request(json!(9), "initialize")), None);
+        assert_eq!(sent(&mut router), vec![(3, jsonrpc::response(json!(9), result))]);
+
+        // Only the first initialized notification reaches the child.
+        let initialized = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        assert_eq!(router.route_client_message(1, initialized.clone()), Some(initialized.clone()));
+        assert_eq!(router.route_client_message(2, initialized), None);
+    }
+
+    #[test]
+    fn unsubscribe_reaches_the_child_with_the_last_subscriber() {
+        let mut router = router(&[1, 2]);
+        assert!(router.route_client_message(1, subscribe("resources/subscribe", 1, "file:///a")).is_some());
+        assert!(router.route_client_message(2, subscribe("resources/subscribe", 1, "file:///a")).is_some());
+
+        let update = jsonrpc::notification("notifications/resources/updated", json!({ "uri": "file:///a" }));
+        router.server_message(update.clone());
+        let mut notified: Vec<ClientId> = sent(&mut router).into_iter().map(|(client, _)| client).collect();
+        notified.sort();
+        assert_eq!(notified, vec![1, 2]);
+
+        // Client 2 still needs updates, so client 1's unsubscribe is answered locally.
+        assert_eq!(router.route_client_message(1, subscribe("resources/unsubscribe", 2, "file:///a")), None);
+        assert_eq!(sent(&mut router), vec![(1, jsonrpc::response(json!(2), json!({})))]);
+        router.server_message(update);
+        assert_eq!(sent(&mut router).into_iter().map(|(client, _)| client).collect::<Vec<_>>(), vec![2]);
+
+        let forward = router.route_client_message(2, subscribe("resources/unsubscribe", 2, "file:///a")).unwrap();
+        assert_eq!(jsonrpc::method(&forward), Some("resources/unsubscribe"));
+        assert!(router.subscriptions.is_empty());
+    }
+
+    #[test]
+    fn cancellations_and_progress_follow_the_rewritten_id() {
+        let mut router = router(&[1, 2]);
+        let mut call = request(json!("call"), "tools/call");
+        call["params"] = json!({ "_meta": { "progressToken": "tok" } });
+        router.route_client_message(1, request(json!("call"), "tools/list"));
+        let forward = router.route_client_message(2, call).unwrap();
+        assert_eq!(forward["id"], 2);
+
+        let progress = jsonrpc::notification("notifications/progress", json!({ "progressToken": "tok", "progress": 1 }));
+        router.server_message(progress.clone());
+        assert_eq!(sent(&mut router), vec![(2, progress)]);
+
+        let cancel = jsonrpc::notification("notifications/cancelled", json!({ "requestId": "call" }));
+        // Client 1 used the same id for another request; only client 2's is cancelled.
+        let forward = router.route_client_message(2, cancel).unwrap();
+        assert_eq!(forward["params"]["requestId"], 2);
+        // A cancellation for an id the client never sent is not forwarded.
+        let unknown = jsonrpc::notification("notifications/cancelled", json!({ "requestId": "other" }));
+        assert_eq!(router.route_client_message(2, unknown), None);
+    }
+}
@@ -0,0 +1,448 @@
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use rand::Rng;
+use serde_json::json;
+use std::process::ExitStatus;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout};
+use tokio::sync::{mpsc, oneshot};
+
+use super::exit::ExitReason;
+use super::group;
+use super::io::{handle_stderr, handle_stdin, handle_stdout, replay_handshake};
+use super::manager::{ChildReport, ProcessManager};
+use super::timeout::InFlight;
+use crate::config::model::{RestartConfig, RestartPolicy, ServerConfig, ShutdownConfig};
+use crate::constants::{DRAIN_POLL_INTERVAL, OUTPUT_DRAIN_TIMEOUT, RESTART_RESET_AFTER};
+use crate::jsonrpc;
+use crate::metrics::metrics;
+use crate::state::SessionState;
+
+/// Keeps a session's child process running according to its restart policy.
+///
+/// The supervisor owns the receiving end of the session's stdin channel, so
+/// a restarted child is wired to the same session without the client noticing.
+pub struct Supervisor {
+    server_config: ServerConfig,
+    process_rx: mpsc::Receiver<String>,
+    websocket_tx: mpsc::Sender<String>,
+    state: Arc<SessionState>,
+    report: Arc<ChildReport>,
+    stop_rx: oneshot::Receiver<()>,
+    restart_rx: mpsc::Receiver<()>,
+    inflight: Arc<InFlight>,
+}
+
+/// Why a supervised child stopped while the session goes on.
+enum Exit {
+    /// The child exited on its own.
+    Exited(ExitStatus),
+    /// A restart was requested and the child was stopped for it.
+    Requested,
+}
+
+impl Supervisor {
+    pub fn new(
+        server_config: ServerConfig,
+        process_rx: mpsc::Receiver<String>,
+        websocket_tx: mpsc::Sender<String>,
+        state: Arc<SessionState>,
+        report: Arc<ChildReport>,
+        stop_rx: oneshot::Receiver<()>,
+        restart_rx: mpsc::Receiver<()>,
+    ) -> Self {
+        let inflight = Arc::new(InFlight::new(server_config.timeout.clone()));
+        Self { server_config, process_rx, websocket_tx, state, report, stop_rx, restart_rx, inflight }
+    }
+
+    /// Drives the child until the session ends or the restart policy gives up.
+    pub async fn run(mut self, mut child: Child) {
+        let mut attempt = 0;
+        let mut restarted = false;
+
+        loop {
+            self.report.pid.store(child.id().unwrap_or(0), Ordering::SeqCst);
+            let started_at = Instant::now();
+            let pgid = child.id();
+
+            let exit = self.supervise(&mut child, restarted).await;
+            if exit.is_none() {
+                stop_child(&mut child, &self.server_config.shutdown).await;
+            }
+            // Whatever the child started must not outlive it.
+            if let Some(pgid) = pgid {
+                group::terminate(pgid, Duration::from_millis(self.server_config.shutdown.kill_timeout_ms)).await;
+            }
+            let Some(exit) = exit else {
+                break;
+            };
+            self.report.pid.store(0, Ordering::SeqCst);
+            let reason = match exit {
+                Exit::Exited(_) => "Server exited",
+                Exit::Requested => "Server restarted",
+            };
+            for error in self.inflight.abandon(reason) {
+                if let Err(e) = self.websocket_tx.send(error.to_string()).await {
+                    debug!("Could not deliver error for abandoned request: {}", e);
+                }
+            }
+
+            let replacement = match exit {
+                Exit::Exited(status) => {
+                    metrics().process_exited(&self.server_config.command, &status);
+                    let reason = ExitReason::classify(&status, &self.server_config.limits);
+                    *self.report.last_exit.lock().unwrap() = Some(reason.clone());
+                    if started_at.elapsed() >= RESTART_RESET_AFTER {
+                        attempt = 0;
+                    }
+                    self.restart(&mut attempt, &status, &reason).await
+                }
+                Exit::Requested => {
+                    attempt = 0;
+                    self.respawn().await
+                }
+            };
+            child = match replacement {
+                Some(child) => child,
+                None => break,
+            };
+            restarted = true;
+        }
+
+        self.report.pid.store(0, Ordering::SeqCst);
+        debug!("Supervisor for '{}' finished", self.server_config.command);
+    }
+
+    /// Wires up the child's stdio and waits for it to exit.
+    /// A restarted child first gets the client's initialize handshake replayed.
+    /// Returns `None` when the session ended or a stop was requested.
+    async fn supervise(&mut self, child: &mut Child, restarted: bool) -> Option<Exit> {
+        let (mut stdin, stdout, stderr) = match take_stdio(child) {
+            Ok(stdio) => stdio,
+            Err(e) => {
+                error!("{}", e);
+                return None;
+            }
+        };
+
+        let handshake = if restarted { self.state.handshake() } else { None };
+        let (replay_tx, replay_rx) = oneshot::channel();
+        let replay_tx = handshake.is_some().then_some(replay_tx);
+
+        let mut stdout_task = tokio::spawn(handle_stdout(
+            stdout,
+            self.websocket_tx.clone(),
+            Arc::clone(&self.state),
+            self.server_config.tools.clone(),
+            Arc::clone(&self.inflight),
+            replay_tx,
+        ));
+        tokio::spawn(handle_stderr(
+            stderr,
+            self.websocket_tx.clone(),
+            Arc::clone(&self.state),
+            self.server_config.stderr.clone(),
+        ));
+
+        let process_rx = &mut self.process_rx;
+        let state = &self.state;
+        let inflight = &self.inflight;
+        let websocket_tx = &self.websocket_tx;
+        let mut forward = Box::pin(async move {
+            if let Some(handshake) = handshake {
+                replay_handshake(&mut stdin, handshake, replay_rx).await?;
+            }
+            handle_stdin(stdin, process_rx, state, inflight, websocket_tx).await
+        });
+
+        let requested = tokio::select! {
+            result = &mut forward => match result {
+                Ok(()) => return None,
+                Err(_) => {
+                    debug!("Child stdin closed, waiting for the process to exit");
+                    false
+                }
+            },
+            _ = &mut self.stop_rx => {
+                if self.state.is_going_away() {
+                    drain(child, &self.inflight, &self.server_config.shutdown).await;
+                }
+                return None;
+            }
+            Some(()) = self.restart_rx.recv() => true,
+            _ = child.wait() => false,
+        };
+        // Closes the child's stdin, asking it to exit.
+        drop(forward);
+        if requested {
+            info!("Restart of '{}' requested, stopping child process", self.server_config.command);
+            stop_child(child, &self.server_config.shutdown).await;
+        }
+
+        let status = match child.wait().await {
+            Ok(status) => status,
+            Err(e) => {
+                error!("Error waiting for child process to exit: {}", e);
+                return None;
+            }
+        };
+
+        // Let the remaining output reach the client before a replacement starts.
+        if tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, &mut stdout_task).await.is_err() {
+            stdout_task.abort();
+        }
+        Some(if requested { Exit::Requested } else { Exit::Exited(status) })
+    }
+
+    /// Waits out the backoff and spawns a replacement child, retrying failed spawns.
+    /// Returns `None` if the child should stay down.
+    async fn restart(&mut self, attempt: &mut u32, status: &ExitStatus, reason: &ExitReason) -> Option<Child> {
+        let restart = &self.server_config.restart;
+        if !should_restart(restart.policy, status) {
+            info!("Child process exited with {}; not restarting", reason);
+            return None;
+        }
+
+        loop {
+            if retries_exhausted(restart, *attempt) {
+                error!(
+                    "Child process exited with {}; giving up after {} restart attempt(s)",
+                    reason, attempt
+                );
+                return None;
+            }
+            *attempt += 1;
+
+            let delay = backoff_delay(restart, *attempt);
+            warn!(
+                "Child process exited with {}; restarting in {:?} (attempt {}/{})",
+                reason, delay, attempt, restart.max_retries
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut self.stop_rx => return None,
+            }
+            if self.state.is_shutdown() {
+                return None;
+            }
+
+            match ProcessManager::spawn_process(&self.server_config) {
+                Ok(child) => {
+                    info!("Restarted child process: {} (pid: {:?})", self.server_config.command, child.id());
+                    metrics().process_restarted(&self.server_config.command);
+                    self.notify_restarted(*attempt, Some(reason)).await;
+                    return Some(child);
+                }
+                Err(e) => error!("Failed to restart child process: {}", e),
+            }
+        }
+    }
+
+    /// Spawns a replacement for a child stopped on request.
+    /// Returns `None` if it could not be started.
+    async fn respawn(&mut self) -> Option<Child> {
+        match ProcessManager::spawn_process(&self.server_config) {
+            Ok(child) => {
+                info!("Restarted child process on request: {} (pid: {:?})", self.server_config.command, child.id());
+                metrics().process_restarted(&self.server_config.command);
+                self.notify_restarted(0, None).await;
+                Some(child)
+            }
+            Err(e) => {
+                error!("Failed to restart child process: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Tells the client that the server behind the session was restarted.
+    /// `attempt` is 0 and `exit` absent for a restart that was requested.
+    async fn notify_restarted(&self, attempt: u32, exit: Option<&ExitReason>) {
+        let notification = jsonrpc::notification(
+            "notifications/runner/restarted",
+            json!({ "attempt": attempt, "exitCode": exit.and_then(ExitReason::code), "exit": exit }),
+        );
+        if let Err(e) = self.websocket_tx.send(notification.to_string()).await {
+            debug!("Could not deliver restart notification: {}", e);
+        }
+    }
+}
+
+fn take_stdio(child: &mut Child) -> Result<(ChildStdin, ChildStdout, ChildStderr)> {
+    let stdin = child.stdin.take().context("Failed to get child stdin")?;
+    let stdout = child.stdout.take().context("Failed to get child stdout")?;
+    let stderr = child.stderr.take().context("Failed to get child stderr")?;
+    Ok((stdin, stdout, stderr))
+}
+
+/// Waits up to the grace period for the child to answer the requests it
+/// was already given. Stdin stays open so the responses can still be read.
+async fn drain(child: &mut Child, inflight: &InFlight, shutdown: &ShutdownConfig) {
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(shutdown.grace_period_ms);
+    let wait = async {
+        while inflight.pending() > 0 {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    };
+    tokio::select! {
+        _ = wait => {}
+        _ = tokio::time::sleep_until(deadline) => {
+            warn!("{} request(s) still pending after the shutdown grace period", inflight.pending());
+        }
+        _ = child.wait() => {}
+    }
+}
+
+/// Stops a child whose stdin has been closed: it gets `exit_timeout_ms` to
+/// exit on its own, then its process group gets SIGTERM and
+/// `kill_timeout_ms` more, then SIGKILL.
+async fn stop_child(child: &mut Child, shutdown: &ShutdownConfig) {
+    debug!("Stopping child process...");
+    if wait_exit(child, shutdown.exit_timeout_ms).await {
+        debug!("Child process exited");
+        return;
+    }
+
+    // The child has not been reaped yet, so its process group id cannot have been reused.
+    #[cfg(unix)]
+    if let Some(pgid) = child.id() {
+        debug!("Child process did not exit, sending SIGTERM to its process group");
+        group::signal(pgid, libc::SIGTERM);
+        if wait_exit(child, shutdown.kill_timeout_ms).await {
+            debug!("Child process terminated");
+            return;
+        }
+        group::signal(pgid, libc::SIGKILL);
+    }
+
+    warn!("Child process did not terminate, killing it");
+    if let Err(e) = child.kill().await {
+        error!("Failed to stop child process: {}", e);
+    }
+    debug!("Child process stopped");
+}
+
+/// Waits up to `timeout_ms` for the child to exit. Returns false on timeout.
+async fn wait_exit(child: &mut Child, timeout_ms: u64) -> bool {
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), child.wait()).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            error!("Error waiting for child process to exit: {}", e);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+fn should_restart(policy: RestartPolicy, status: &ExitStatus) -> bool {
+    match policy {
+        RestartPolicy::Never => false,
+        RestartPolicy::OnFailure => !status.success(),
+        RestartPolicy::Always => true,
+    }
+}
+
+/// Returns true once `attempt` restarts in a row have been made.
+fn retries_exhausted(restart: &RestartConfig, attempt: u32) -> bool {
+    attempt >= restart.max_retries
+}
+
+/// Exponential backoff capped at `max_backoff_ms`, randomized by `jitter`.
+fn backoff_delay(restart: &RestartConfig, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(20);
+    let base = restart
+        .initial_backoff_ms
+        .saturating_mul(1 << exponent)
+        .min(restart.max_backoff_ms);
+
+    let jitter = restart.jitter.clamp(0.0, 1.0);
+    let factor = if jitter > 0.0 {
+        rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter)
+    } else {
+        1.0
+    };
+    Duration::from_millis((base as f64 * factor) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restart(initial_backoff_ms: u64, max_backoff_ms: u64, jitter: f64) -> RestartConfig {
+        RestartConfig { initial_backoff_ms, max_backoff_ms, jitter, ..RestartConfig::default() }
+    }
+
+    #[cfg(unix)]
+    fn status(code: i32) -> ExitStatus {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restart_depends_on_the_policy() {
+        let (success, failure) = (status(0), status(1));
+        assert!(!should_restart(RestartPolicy::Never, &success));
+        assert!(!should_restart(RestartPolicy::Never, &failure));
+        assert!(!should_restart(RestartPolicy::OnFailure, &success));
+        assert!(should_restart(RestartPolicy::OnFailure, &failure));
+        assert!(should_restart(RestartPolicy::Always, &success));
+        assert!(should_restart(RestartPolicy::Always, &failure));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_signal_counts_as_a_failure() {
+        use std::os::unix::process::ExitStatusExt;
+        assert!(should_restart(RestartPolicy::OnFailure, &ExitStatus::from_raw(libc::SIGKILL)));
+    }
+
+    #[test]
+    fn retries_stop_at_the_maximum() {
+        let config = RestartConfig { max_retries: 3, ..RestartConfig::default() };
+        assert!(!retries_exhausted(&config, 0));
+        assert!(!retries_exhausted(&config, 2));
+        assert!(retries_exhausted(&config, 3));
+        assert!(retries_exhausted(&config, 4));
+
+        let config = RestartConfig { max_retries: 0, ..RestartConfig::default() };
+        assert!(retries_exhausted(&config, 0));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let config = restart(500, 30_000, 0.0);
+        let delays: Vec<u64> = (1..=8).map(|attempt| backoff_delay(&config, attempt).as_millis() as u64).collect();
+        assert_eq!(delays, [500, 1_000, 2_000, 4_000, 8_000, 16_000, 30_000, 30_000]);
+        assert_eq!(backoff_delay(&config, 0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(&config, u32::MAX), Duration::from_millis(30_000));
+    }
+
+    #[test]
+    fn backoff_never_exceeds_a_small_cap() {
+        let config = restart(u64::MAX, 1_000, 0.0);
+        assert_eq!(backoff_delay(&config, 1), Duration::from_millis(1_000));
+        assert_eq!(backoff_delay(&config, 30), Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn jitter_stays_within_its_fraction() {
+        let config = restart(1_000, 30_000, 0.2);
+        for _ in 0..200 {
+            let delay = backoff_delay(&config, 1).as_millis();
+            assert!((800..=1_200).contains(&delay), "{} ms", delay);
+        }
+    }
+
+    #[test]
+    fn jitter_is_clamped() {
+        let config = restart(1_000, 30_000, 5.0);
+        for _ in 0..200 {
+            assert!(backoff_delay(&config, 1) <= Duration::from_millis(2_000));
+        }
+        let config = restart(1_000, 30_000, -1.0);
+        assert_eq!(backoff_delay(&config, 1), Duration::from_millis(1_000));
+    }
+}
@@ -0,0 +1,315 @@
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::model::TimeoutConfig;
+use crate::constants::LATE_RESPONSE_WINDOW;
+use crate::jsonrpc;
+use crate::metrics::metrics;
+
+/// Requests forwarded to the child that are still waiting for a response.
+///
+/// Every request is tracked so its latency can be recorded when the response
+/// arrives. When a request whose method has a timeout configured expires the
+/// client gets an error response in its place, and a late response from the
+/// child is dropped.
+#[derive(Debug)]
+pub struct InFlight {
+    config: TimeoutConfig,
+    inner: Mutex<Tracked>,
+}
+
+#[derive(Debug, Default)]
+struct Tracked {
+    /// Pending requests keyed by serialized id.
+    requests: HashMap<String, Request>,
+    /// Ids that timed out, and when, whose late response must not reach the client.
+    expired: HashMap<String, Instant>,
+}
+
+#[derive(Debug)]
+struct Request {
+    id: Value,
+    method: String,
+    started: Instant,
+    /// Timeout and deadline, if one is configured for the method.
+    timeout: Option<(Duration, Instant)>,
+}
+
+/// A request that timed out, with the messages to send on its behalf.
+pub struct Expired {
+    /// Error response for the client.
+    pub error: Value,
+    /// Cancellation for the child.
+    pub cancellation: Value,
+}
+
+impl InFlight {
+    pub fn new(config: TimeoutConfig) -> Self {
+        Self { config, inner: Mutex::new(Tracked::default()) }
+    }
+
+    /// Starts tracking the requests in a message about to be written to the child.
+    /// A client cancellation stops tracking the request it cancels.
+    pub fn track(&self, message: &Value) {
+        if let Value::Array(batch) = message {
+            batch.iter().for_each(|message| self.track(message));
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if jsonrpc::method(message) == Some("notifications/cancelled") {
+            inner.requests.remove(&message["params"]["requestId"].to_string());
+            return;
+        }
+        if !jsonrpc::is_request(message) {
+            return;
+        }
+        let method = jsonrpc::method(message).unwrap_or_default();
+        let started = Instant::now();
+        inner.requests.insert(
+            message["id"].to_string(),
+            Request {
+                id: message["id"].clone(),
+                method: method.to_string(),
+                started,
+                timeout: self.timeout_for(method).map(|timeout| (timeout, started + timeout)),
+            },
+        );
+    }
+
+    /// Marks the responses in a line written by the child as answered.
+    /// Returns the line to forward, or `None` if only late responses remained.
+    pub fn complete(&self, line: String) -> Option<String> {
+        let Ok(mut message) = serde_json::from_str::<Value>(&line) else {
+            return Some(line);
+        };
+        let mut inner = self.inner.lock().unwrap();
+        match &mut message {
+            Value::Array(batch) => {
+                let before = batch.len();
+                batch.retain(|message| inner.complete(message));
+                if batch.is_empty() {
+                    return None;
+                }
+                if batch.len() != before {
+                    return Some(message.to_string());
+                }
+                Some(line)
+            }
+            message => inner.complete(message).then_some(line),
+        }
+    }
+
+    /// Returns the number of requests still waiting for a response.
+    pub fn pending(&self) -> usize {
+        self.inner.lock().unwrap().requests.len()
+    }
+
+    /// Returns the earliest deadline of the tracked requests.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.inner
+            .lock()
+            .unwrap()
+            .requests
+            .values()
+            .filter_map(|request| request.timeout.map(|(_, deadline)| deadline))
+            .min()
+    }
+
+    /// Stops tracking every request; called when the child exits, since it
+    /// will never answer them. Returns an error response for each one.
+    pub fn abandon(&self, reason: &str) -> Vec<Value> {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .requests
+            .drain()
+            .map(|(_, request)| {
+                debug!("Abandoning request '{}' (id: {}): {}", request.method, request.id, reason);
+                jsonrpc::error_response(request.id, jsonrpc::INTERNAL_ERROR, reason)
+            })
+            .collect()
+    }
+
+    /// Stops tracking every request whose deadline has passed.
+    pub fn expire(&self) -> Vec<Expired> {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        // Children that honour the cancellation never answer; forget them eventually.
+        inner.expired.retain(|_, at| now.duration_since(*at) < LATE_RESPONSE_WINDOW);
+        let ids: Vec<String> = inner
+            .requests
+            .iter()
+            .filter(|(_, request)| request.timeout.is_some_and(|(_, deadline)| deadline <= now))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut expired = Vec::new();
+        for key in ids {
+            let Some(Request { id, method, timeout: Some((timeout, _)), .. }) = inner.requests.remove(&key) else {
+                continue;
+            };
+            inner.expired.insert(key, now);
+            warn!("Request '{}' (id: {}) timed out after {:?}", method, id, timeout);
+            let reason = format!("Request timed out after {:?}", timeout);
+            expired.push(Expired {
+                error: jsonrpc::error_response(id.clone(), jsonrpc::REQUEST_TIMEOUT, &reason),
+                cancellation: jsonrpc::notification(
+                    "notifications/cancelled",
+                    json!({ "requestId": id, "reason": reason }),
+                ),
+            });
+        }
+        expired
+    }
+
+    fn timeout_for(&self, method: &str) -> Option<Duration> {
+        self.config
+            .methods
+            .get(method)
+            .copied()
+            .or(self.config.request_ms)
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis)
+    }
+}
+
+impl Tracked {
+    /// Returns false for a late response to a request that already timed out.
+    fn complete(&mut self, message: &Value) -> bool {
+        if !jsonrpc::is_response(message) {
+            return true;
+        }
+        let key = message["id"].to_string();
+        if self.expired.remove(&key).is_some() {
+            debug!("Dropping late response to timed out request {}", key);
+            return false;
+        }
+        if let Some(request) = self.requests.remove(&key) {
+            metrics().request_completed(&request.method, request.started.elapsed());
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inflight(request_ms: Option<u64>, methods: &[(&str, u64)]) -> InFlight {
+        let methods: HashMap<String, u64> = methods.iter().map(|(method, ms)| (method.to_string(), *ms)).collect();
+        InFlight::new(TimeoutConfig { request_ms, methods })
+    }
+
+    fn request(id: i64, method: &str) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method })
+    }
+
+    fn response(id: i64) -> String {
+        jsonrpc::response(json!(id), json!({})).to_string()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn method_timeout_overrides_the_default() {
+        let inflight = inflight(Some(1_000), &[("tools/call", 5_000), ("ping", 0)]);
+        inflight.track(&json!([request(1, "tools/list"), request(2, "tools/call"), request(3, "ping")]));
+        assert_eq!(inflight.pending(), 3);
+        assert_eq!(inflight.next_deadline(), Some(Instant::now() + Duration::from_millis(1_000)));
+
+        tokio::time::advance(Duration::from_millis(999)).await;
+        assert!(inflight.expire().is_empty());
+
+        tokio::time::advance(Duration::from_millis(1)).await;
+        let expired = inflight.expire();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].error["id"], 1);
+        assert_eq!(expired[0].error["error"]["code"], jsonrpc::REQUEST_TIMEOUT);
+        assert_eq!(expired[0].error["error"]["message"], "Request timed out after 1s");
+        assert_eq!(inflight.next_deadline(), Some(Instant::now() + Duration::from_millis(4_000)));
+
+        tokio::time::advance(Duration::from_millis(4_000)).await;
+        let expired = inflight.expire();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].error["id"], 2);
+        // A timeout of 0 disables it; the request is still tracked for its latency.
+        assert_eq!(inflight.pending(), 1);
+        assert_eq!(inflight.next_deadline(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expiry_cancels_the_request_in_the_child() {
+        let inflight = inflight(Some(100), &[]);
+        inflight.track(&request(7, "tools/call"));
+        tokio::time::advance(Duration::from_millis(100)).await;
+
+        let expired = inflight.expire();
+        let cancellation = &expired[0].cancellation;
+        assert_eq!(jsonrpc::method(cancellation), Some("notifications/cancelled"));
+        assert_eq!(cancellation["params"]["requestId"], 7);
+        assert!(cancellation.get("id").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_responses_are_dropped() {
+        let inflight = inflight(Some(100), &[]);
+        inflight.track(&json!([request(1, "a"), request(2, "b")]));
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_eq!(inflight.expire().len(), 2);
+
+        assert_eq!(inflight.complete(response(1)), None);
+        // A late member of a batch is removed from the array.
+        let batch = format!("[{},{}]", response(2), response(3));
+        let forwarded: Value = serde_json::from_str(&inflight.complete(batch).unwrap()).unwrap();
+        assert_eq!(forwarded, json!([jsonrpc::response(json!(3), json!({}))]));
+        // Only the first late response is dropped.
+        assert_eq!(inflight.complete(response(1)), Some(response(1)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_responses_are_forgotten_after_the_window() {
+        let inflight = inflight(Some(100), &[]);
+        inflight.track(&request(1, "a"));
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_eq!(inflight.expire().len(), 1);
+
+        tokio::time::advance(LATE_RESPONSE_WINDOW).await;
+        assert!(inflight.expire().is_empty());
+        assert_eq!(inflight.complete(response(1)), Some(response(1)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn answered_and_cancelled_requests_stop_being_tracked() {
+        let inflight = inflight(Some(100), &[]);
+        inflight.track(&json!([request(1, "a"), request(2, "b")]));
+        inflight.track(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }));
+
+        assert_eq!(inflight.complete(response(1)), Some(response(1)));
+        inflight.track(&jsonrpc::notification("notifications/cancelled", json!({ "requestId": 2 })));
+        assert_eq!(inflight.pending(), 0);
+
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert!(inflight.expire().is_empty());
+        // Other output passes through untouched.
+        let line = "not json".to_string();
+        assert_eq!(inflight.complete(line.clone()), Some(line));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn abandon_fails_every_pending_request() {
+        let inflight = inflight(None, &[]);
+        inflight.track(&json!([request(1, "a"), request(2, "b")]));
+        assert_eq!(inflight.next_deadline(), None);
+
+        let mut errors = inflight.abandon("Server exited");
+        errors.sort_by_key(|error| error["id"].as_i64());
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0]["id"], 1);
+        assert_eq!(errors[1]["error"]["code"], jsonrpc::INTERNAL_ERROR);
+        assert_eq!(errors[1]["error"]["message"], "Server exited");
+        assert_eq!(inflight.pending(), 0);
+        assert!(inflight.abandon("Server exited").is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+//! Resolution of the configured server a client asks for.
+
+use std::fmt;
+
+use crate::config::model::Config;
+
+/// Why a requested server could not be resolved.
+#[derive(Debug)]
+pub enum RouteError {
+    /// No server was named and no default server is configured.
+    NoServer,
+    /// The named server is not configured.
+    UnknownServer(String),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::NoServer => write!(f, "No server specified"),
+            RouteError::UnknownServer(name) => write!(f, "Unknown server: {}", name),
+        }
+    }
+}
+
+/// Resolves the server named by a request.
+///
+/// The name is taken from the first segment of `path` (`/github`) or,
+/// failing that, from the `server` query parameter (`?server=github`).
+/// Requests naming neither fall back to `default_server`. The configured
+/// gateway resolves like any other server name.
+pub fn resolve_server(path: &str, query: Option<&str>, config: &Config) -> Result<String, RouteError> {
+    let requested = path_server(path).or_else(|| query.and_then(|query| query_param(query, "server")));
+    let name = requested
+        .or_else(|| config.default_server.clone())
+        .ok_or(RouteError::NoServer)?;
+
+    if !config.servers.contains_key(&name) && !config.is_gateway(&name) {
+        return Err(RouteError::UnknownServer(name));
+    }
+    Ok(name)
+}
+
+/// Returns the decoded value of a query parameter, if present and non-empty.
+pub fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+        .filter(|value| !value.is_empty())
+}
+
+fn path_server(path: &str) -> Option<String> {
+    path.split('/')
+        .find(|segment| !segment.is_empty())
+        .map(percent_decode)
+}
+
+/// Decodes `%XX` escapes and `+` in a URL component.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = (bytes[i + 1] as char).to_digit(16).zip((bytes[i + 2] as char).to_digit(16));
+                match hex {
+                    Some((high, low)) => {
+                        decoded.push((high * 16 + low) as u8);
+                        i += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(default_server: Option<&str>) -> Config {
+        serde_json::from_value(json!({
+            "servers": {
+                "github": { "command": "github-server" },
+                "my server": { "command": "other-server" }
+            },
+            "default_server": default_server
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn percent_decode_escapes() {
+        assert_eq!(percent_decode("a%2Fb"), "a/b");
+        assert_eq!(percent_decode("a%2fb"), "a/b");
+        assert_eq!(percent_decode("my+server%20x"), "my server x");
+        assert_eq!(percent_decode("%E2%9C%93"), "\u{2713}");
+    }
+
+    #[test]
+    fn percent_decode_keeps_invalid_escapes() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("%%41"), "%A");
+        assert_eq!(percent_decode("%FF"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn query_param_lookup() {
+        assert_eq!(query_param("server=github&token=a%2Bb", "token").as_deref(), Some("a+b"));
+        assert_eq!(query_param("servers=x&server=github", "server").as_deref(), Some("github"));
+        assert_eq!(query_param("server=&token=x", "server"), None);
+        assert_eq!(query_param("server", "server"), None);
+        assert_eq!(query_param("", "server"), None);
+    }
+
+    #[test]
+    fn path_takes_precedence_over_query_and_default() {
+        let config = config(Some("github"));
+        assert_eq!(resolve_server("/my%20server", Some("server=github"), &config).unwrap(), "my server");
+        assert_eq!(resolve_server("/", Some("server=my+server"), &config).unwrap(), "my server");
+        assert_eq!(resolve_server("//", None, &config).unwrap(), "github");
+        assert_eq!(resolve_server("/github/mcp", None, &config).unwrap(), "github");
+    }
+
+    #[test]
+    fn encoded_slash_stays_in_the_segment() {
+        let config = config(None);
+        assert!(matches!(
+            resolve_server("/github%2Fmcp", None, &config),
+            Err(RouteError::UnknownServer(name)) if name == "github/mcp"
+        ));
+    }
+
+    #[test]
+    fn unnamed_request_without_default_fails() {
+        let config = config(None);
+        assert!(matches!(resolve_server("/", None, &config), Err(RouteError::NoServer)));
+        assert!(matches!(resolve_server("/", Some("server="), &config), Err(RouteError::NoServer)));
+    }
+}
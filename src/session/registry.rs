@@ -0,0 +1,367 @@
+use anyhow::{Context, Result};
+use futures_util::future::{join, join_all};
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::config::model::{Config, ServerConfig, ServerMode, ToolPolicy};
+use crate::metrics::metrics;
+use crate::process::{ChildStatus, ClientId, Gateway, ProcessManager, SharedProcess};
+use crate::state::SessionState;
+
+/// Identifier assigned to each accepted client session.
+pub type SessionId = u64;
+
+/// A client session together with the child process serving it.
+pub struct Session {
+    pub id: SessionId,
+    pub peer_addr: SocketAddr,
+    pub server_name: String,
+    pub state: Arc<SessionState>,
+    pub started_at: Instant,
+    backend: Backend,
+}
+
+/// The child process a session talks to.
+enum Backend {
+    /// A child started exclusively for this session.
+    Dedicated(ProcessManager),
+    /// A child shared with other sessions of the same server.
+    Shared {
+        process: Arc<SharedProcess>,
+        client: ClientId,
+    },
+    /// Children for every member of the gateway, started for this session.
+    Gateway(Arc<Gateway>),
+}
+
+impl Session {
+    /// Returns the OS process id of the session's child, if it is running.
+    pub async fn pid(&self) -> Option<u32> {
+        match &self.backend {
+            Backend::Dedicated(process) => process.pid(),
+            Backend::Shared { process, .. } => process.pid().await,
+            Backend::Gateway(_) => None,
+        }
+    }
+
+    /// Returns a snapshot of the session, with the children's recent stderr lines if `stderr` is set.
+    pub async fn info(&self, stderr: bool) -> SessionInfo {
+        let (messages_received, messages_sent) = self.state.message_counts();
+        let (mode, children) = match &self.backend {
+            Backend::Dedicated(process) => (
+                "isolated",
+                vec![ChildStatus {
+                    server: self.server_name.clone(),
+                    pid: process.pid(),
+                    last_exit: process.last_exit(),
+                    stderr: stderr.then(|| self.state.recent_stderr()),
+                }],
+            ),
+            Backend::Shared { process, .. } => ("shared", vec![process.status(stderr).await]),
+            Backend::Gateway(gateway) => ("gateway", gateway.children(stderr).await),
+        };
+        SessionInfo {
+            id: self.id,
+            peer_addr: self.peer_addr.to_string(),
+            server: self.server_name.clone(),
+            mode,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            messages_received,
+            messages_sent,
+            children,
+        }
+    }
+
+    /// Returns what is needed to replace the session's child (or children),
+    /// so that the restart can run without holding the registry lock.
+    fn restarter(&self) -> Restarter {
+        match &self.backend {
+            Backend::Dedicated(process) => Restarter::Requested(process.restart()),
+            Backend::Shared { process, .. } => Restarter::Shared(Arc::clone(process)),
+            Backend::Gateway(gateway) => Restarter::Gateway(Arc::clone(gateway)),
+        }
+    }
+
+    /// Stops the session's child (or detaches from a shared one).
+    /// A session going away keeps delivering responses until its child is stopped.
+    async fn close(self) {
+        let going_away = self.state.is_going_away();
+        if !going_away {
+            self.state.shutdown();
+        }
+        match self.backend {
+            Backend::Dedicated(mut process) => process.shutdown().await,
+            Backend::Shared { process, client } => process.detach(client).await,
+            Backend::Gateway(gateway) => {
+                if going_away {
+                    gateway.go_away();
+                }
+                gateway.shutdown().await
+            }
+        }
+        self.state.shutdown();
+    }
+}
+
+/// A restart of a session's child (or children) about to be carried out.
+enum Restarter {
+    /// A dedicated child's supervisor was already asked; false if it is gone.
+    Requested(bool),
+    Shared(Arc<SharedProcess>),
+    Gateway(Arc<Gateway>),
+}
+
+impl Restarter {
+    /// Returns false if a child is no longer supervised.
+    async fn run(self) -> bool {
+        match self {
+            Restarter::Requested(restarted) => restarted,
+            Restarter::Shared(process) => process.restart().await,
+            Restarter::Gateway(gateway) => gateway.restart().await,
+        }
+    }
+}
+
+/// A session as reported by the admin API.
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    pub peer_addr: String,
+    pub server: String,
+    /// `isolated`, `shared` or `gateway`.
+    pub mode: &'static str,
+    pub uptime_secs: u64,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub children: Vec<ChildStatus>,
+}
+
+/// What the connection handler needs to drive a freshly opened session.
+pub struct SessionHandle {
+    pub id: SessionId,
+    pub state: Arc<SessionState>,
+    pub process_tx: mpsc::Sender<String>,
+    /// Tool policy the transport enforces on the client's messages.
+    pub tools: ToolPolicy,
+}
+
+/// Registry of active sessions keyed by session id.
+#[derive(Default)]
+pub struct SessionRegistry {
+    next_id: AtomicU64,
+    sessions: Mutex<HashMap<SessionId, Session>>,
+    shared: Mutex<HashMap<String, Arc<SharedProcess>>>,
+    /// Set once the server shuts down; no further sessions are opened.
+    closing: AtomicBool,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connects a new client to its server and registers the session.
+    ///
+    /// Isolated servers get a dedicated child process; shared servers attach
+    /// the client to the running child, starting it on first use. The
+    /// gateway starts a child for each of its members.
+    pub async fn open(
+        &self,
+        peer_addr: SocketAddr,
+        server_name: &str,
+        config: &Config,
+        websocket_tx: mpsc::Sender<String>,
+    ) -> Result<SessionHandle> {
+        if self.closing.load(Ordering::SeqCst) {
+            anyhow::bail!("Server is shutting down");
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let state = Arc::new(SessionState::new());
+
+        let (backend, process_tx, tools) = if config.is_gateway(server_name) {
+            let (backend, process_tx) = Self::open_gateway(config, websocket_tx).await?;
+            // Members' tool policies are enforced by the gateway itself.
+            (backend, process_tx, ToolPolicy::default())
+        } else {
+            let server_config = config
+                .servers
+                .get(server_name)
+                .with_context(|| format!("Unknown server: {}", server_name))?;
+            let (backend, process_tx) = self.open_server(server_name, server_config, websocket_tx, &state).await?;
+            (backend, process_tx, server_config.tools.clone())
+        };
+
+        let session = Session {
+            id,
+            peer_addr,
+            server_name: server_name.to_string(),
+            state: Arc::clone(&state),
+            started_at: Instant::now(),
+            backend,
+        };
+        debug!(
+            "Registered session {} for {} (server: {}, pid: {:?})",
+            id, peer_addr, server_name, session.pid().await
+        );
+        let mut sessions = self.sessions.lock().await;
+        if self.closing.load(Ordering::SeqCst) {
+            drop(sessions);
+            session.close().await;
+            anyhow::bail!("Server is shutting down");
+        }
+        sessions.insert(id, session);
+        drop(sessions);
+        metrics().session_opened();
+
+        Ok(SessionHandle { id, state, process_tx, tools })
+    }
+
+    /// Starts or attaches to the child process of a single server.
+    async fn open_server(
+        &self,
+        server_name: &str,
+        server_config: &ServerConfig,
+        websocket_tx: mpsc::Sender<String>,
+        state: &Arc<SessionState>,
+    ) -> Result<(Backend, mpsc::Sender<String>)> {
+        Ok(match server_config.mode {
+            ServerMode::Isolated => {
+                let mut process = ProcessManager::new();
+                let process_tx = process
+                    .start_process(server_config, websocket_tx, Arc::clone(state))
+                    .await?;
+                (Backend::Dedicated(process), process_tx)
+            }
+            ServerMode::Shared => {
+                let process = self.shared_process(server_name, server_config).await?;
+                let (client, process_tx) = process.attach(websocket_tx).await;
+                (Backend::Shared { process, client }, process_tx)
+            }
+        })
+    }
+
+    /// Starts the gateway's member children.
+    async fn open_gateway(config: &Config, websocket_tx: mpsc::Sender<String>) -> Result<(Backend, mpsc::Sender<String>)> {
+        let gateway_config = config.gateway.as_ref().context("Gateway is not configured")?;
+        let mut members: Vec<(String, ServerConfig)> = if gateway_config.servers.is_empty() {
+            config.servers.iter().map(|(name, server)| (name.clone(), server.clone())).collect()
+        } else {
+            gateway_config
+                .servers
+                .iter()
+                .filter_map(|name| config.servers.get(name).map(|server| (name.clone(), server.clone())))
+                .collect()
+        };
+        members.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let (gateway, process_tx) = Gateway::start(&gateway_config.separator, members, websocket_tx).await?;
+        Ok((Backend::Gateway(gateway), process_tx))
+    }
+
+    /// Removes a session and stops its child process.
+    /// Returns false if there is no such session.
+    pub async fn close(&self, id: SessionId) -> bool {
+        let session = self.sessions.lock().await.remove(&id);
+        let Some(session) = session else {
+            return false;
+        };
+        info!("Closing session {} ({})", id, session.peer_addr);
+        metrics().session_closed();
+        session.close().await;
+        true
+    }
+
+    /// Returns a snapshot of every active session, ordered by id.
+    pub async fn list(&self) -> Vec<SessionInfo> {
+        let sessions = self.sessions.lock().await;
+        let mut infos = Vec::with_capacity(sessions.len());
+        for session in sessions.values() {
+            infos.push(session.info(false).await);
+        }
+        infos.sort_by_key(|info| info.id);
+        infos
+    }
+
+    /// Returns a snapshot of one session, including its children's recent stderr lines.
+    pub async fn get(&self, id: SessionId) -> Option<SessionInfo> {
+        let sessions = self.sessions.lock().await;
+        match sessions.get(&id) {
+            Some(session) => Some(session.info(true).await),
+            None => None,
+        }
+    }
+
+    /// Replaces the child process(es) of a session regardless of the restart policy.
+    /// Returns `None` if there is no such session and `Some(false)` if its child
+    /// is no longer supervised.
+    pub async fn restart(&self, id: SessionId) -> Option<bool> {
+        let restarter = {
+            let sessions = self.sessions.lock().await;
+            let session = sessions.get(&id)?;
+            info!("Restart requested for session {} (server: {})", id, session.server_name);
+            session.restarter()
+        };
+        Some(restarter.run().await)
+    }
+
+    /// Closes every registered session and stops the shared processes.
+    ///
+    /// Sessions stop accepting requests while their children get the shutdown
+    /// grace period to answer the pending ones. Sessions on a shared child are
+    /// closed once it has stopped, so they receive its last responses.
+    pub async fn shutdown_all(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+        let sessions: Vec<Session> = self.sessions.lock().await.drain().map(|(_, s)| s).collect();
+        let shared: Vec<Arc<SharedProcess>> = self.shared.lock().await.drain().map(|(_, p)| p).collect();
+        debug!("Closing {} active session(s)", sessions.len());
+        for session in &sessions {
+            session.state.go_away();
+        }
+
+        let (on_shared, sessions): (Vec<Session>, Vec<Session>) = sessions
+            .into_iter()
+            .partition(|session| matches!(session.backend, Backend::Shared { .. }));
+        let close_sessions = join_all(sessions.into_iter().map(|session| {
+            metrics().session_closed();
+            session.close()
+        }));
+        let stop_shared = join_all(shared.iter().map(|process| async move {
+            process.go_away();
+            process.shutdown().await;
+        }));
+        join(close_sessions, stop_shared).await;
+
+        for session in on_shared {
+            metrics().session_closed();
+            session.close().await;
+        }
+    }
+
+    /// Returns the running shared process for a server, starting it if needed.
+    async fn shared_process(&self, server_name: &str, server_config: &ServerConfig) -> Result<Arc<SharedProcess>> {
+        let mut shared = self.shared.lock().await;
+        if let Some(process) = shared.get(server_name).filter(|process| process.is_running()) {
+            if process.serves(server_config) {
+                return Ok(Arc::clone(process));
+            }
+        }
+
+        let process = SharedProcess::start(server_name, server_config).await?;
+        if let Some(previous) = shared.insert(server_name.to_string(), Arc::clone(&process)) {
+            // The definition was reloaded; clients still attached keep the old child.
+            previous.retire().await;
+        }
+        Ok(process)
+    }
+
+    /// Returns the number of active sessions.
+    pub async fn count(&self) -> usize {
+        self.sessions.lock().await.len()
+    }
+}
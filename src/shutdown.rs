@@ -1,15 +1,12 @@
 use log::info;
 use tokio::signal;
-use std::sync::atomic::Ordering;
-
-use crate::state::SHUTDOWN;
 
 /// Handles shutdown signals for the application.
-/// Listens for Ctrl+C and termination signals (on Unix systems),
-/// and sets the global shutdown flag when received.
+/// Listens for Ctrl+C and termination signals (on Unix systems)
+/// and resolves once one of them is received.
 pub async fn shutdown_signal() {
     wait_for_shutdown_signal().await;
-    initiate_shutdown();
+    info!("Initiating shutdown sequence");
 }
 
 /// Waits for either Ctrl+C or termination signal.
@@ -23,6 +20,23 @@ async fn wait_for_shutdown_signal() {
     }
 }
 
+/// Calls `on_hangup` every time SIGHUP is received (Unix only).
+#[cfg(unix)]
+pub async fn handle_hangup<F: Fn()>(on_hangup: F) {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+        .expect("Failed to install SIGHUP handler");
+    while hangup.recv().await.is_some() {
+        info!("Hangup signal received");
+        on_hangup();
+    }
+}
+
+/// Placeholder for non-Unix systems.
+#[cfg(not(unix))]
+pub async fn handle_hangup<F: Fn()>(_on_hangup: F) {
+    std::future::pending::<()>().await
+}
+
 /// Sets up Ctrl+C signal handler.
 async fn setup_ctrl_c() {
     signal::ctrl_c()
@@ -43,10 +57,4 @@ async fn setup_terminate() {
 #[cfg(not(unix))]
 async fn setup_terminate() {
     std::future::pending::<()>().await
-}
-
-/// Initiates the shutdown process by setting the global shutdown flag.
-fn initiate_shutdown() {
-    info!("Initiating shutdown sequence");
-    SHUTDOWN.store(true, Ordering::SeqCst);
 }
\ No newline at end of file
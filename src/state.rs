@@ -1,9 +1,146 @@
-use std::sync::atomic::AtomicBool;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
 
-/// Indicates whether a client is currently connected to the WebSocket server.
-/// Used to ensure only one client can be connected at a time.
-pub static CONNECTED: AtomicBool = AtomicBool::new(false);
+use crate::jsonrpc;
+use crate::logging::LogLevel;
 
-/// Global shutdown flag to signal all components to terminate.
-/// When set to true, all async tasks should gracefully shut down.
-pub static SHUTDOWN: AtomicBool = AtomicBool::new(false);
\ No newline at end of file
+/// State shared between a single client session and the tasks serving it.
+/// Each WebSocket connection owns its own instance, so one client
+/// disconnecting never affects another.
+#[derive(Debug, Default)]
+pub struct SessionState {
+    /// Shutdown flag for this session.
+    /// When set to true, all async tasks of the session should terminate.
+    shutdown: AtomicBool,
+    /// Wakes the tasks waiting for the shutdown flag.
+    closed: Notify,
+    /// Set when the runner itself shuts down: new requests are refused while
+    /// pending ones drain, and the client is told the server is going away.
+    going_away: AtomicBool,
+    /// The client's initialize handshake, replayed if the child is restarted.
+    handshake: Mutex<InitializeHandshake>,
+    /// Minimum level of forwarded log notifications, set by `logging/setLevel`.
+    log_level: Mutex<LogLevel>,
+    /// The most recent lines the child wrote to stderr.
+    stderr: Mutex<VecDeque<String>>,
+    /// Messages received from the client.
+    messages_received: AtomicU64,
+    /// Messages sent to the client.
+    messages_sent: AtomicU64,
+}
+
+/// The client's side of the MCP initialize handshake.
+#[derive(Debug, Clone, Default)]
+pub struct InitializeHandshake {
+    /// The `initialize` request as sent by the client.
+    pub request: Option<Value>,
+    /// The `notifications/initialized` notification that followed it.
+    pub initialized: Option<Value>,
+}
+
+impl SessionState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true once the session has been asked to shut down.
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Signals every task of the session to terminate.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.closed.notify_waiters();
+    }
+
+    /// Resolves once the session has been asked to shut down.
+    pub async fn wait_shutdown(&self) {
+        let closed = self.closed.notified();
+        if self.is_shutdown() {
+            return;
+        }
+        closed.await;
+    }
+
+    /// Returns true once the runner has started shutting down the session.
+    pub fn is_going_away(&self) -> bool {
+        self.going_away.load(Ordering::SeqCst)
+    }
+
+    /// Marks the session as closing because the runner shuts down.
+    pub fn go_away(&self) {
+        self.going_away.store(true, Ordering::SeqCst);
+    }
+
+    /// Records the handshake messages of a message (or batch) headed for the child.
+    pub fn record_client_message(&self, message: &Value) {
+        if let Value::Array(batch) = message {
+            batch.iter().for_each(|message| self.record_client_message(message));
+            return;
+        }
+
+        let mut handshake = self.handshake.lock().unwrap();
+        match jsonrpc::method(message) {
+            Some("initialize") if jsonrpc::is_request(message) => {
+                handshake.request = Some(message.clone());
+                handshake.initialized = None;
+            }
+            Some("notifications/initialized") => handshake.initialized = Some(message.clone()),
+            Some("logging/setLevel") => {
+                if let Some(level) = message["params"]["level"].as_str().and_then(LogLevel::parse) {
+                    *self.log_level.lock().unwrap() = level;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the minimum level the client wants log notifications for.
+    pub fn log_level(&self) -> LogLevel {
+        *self.log_level.lock().unwrap()
+    }
+
+    /// Appends a stderr line, keeping at most `capacity` lines.
+    pub fn push_stderr(&self, line: &str, capacity: usize) {
+        let mut stderr = self.stderr.lock().unwrap();
+        while stderr.len() >= capacity.max(1) {
+            stderr.pop_front();
+        }
+        if capacity > 0 {
+            stderr.push_back(line.to_string());
+        }
+    }
+
+    /// Returns the most recent stderr lines of the session's child, oldest first.
+    pub fn recent_stderr(&self) -> Vec<String> {
+        self.stderr.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Counts a message received from the client.
+    pub fn count_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a message sent to the client.
+    pub fn count_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of messages received from and sent to the client.
+    pub fn message_counts(&self) -> (u64, u64) {
+        (
+            self.messages_received.load(Ordering::Relaxed),
+            self.messages_sent.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Returns the captured handshake, if the client has sent one.
+    pub fn handshake(&self) -> Option<InitializeHandshake> {
+        let handshake = self.handshake.lock().unwrap();
+        handshake.request.is_some().then(|| handshake.clone())
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use log::{debug, error, info};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::model::TlsConfig;
+use crate::constants::TLS_RELOAD_INTERVAL;
+
+/// TLS acceptor for the WebSocket listener whose certificates can be
+/// swapped at runtime.
+///
+/// Reloading only affects connections accepted afterwards; established
+/// sessions keep the configuration they were accepted with.
+pub struct TlsContext {
+    config: TlsConfig,
+    acceptor: RwLock<TlsAcceptor>,
+    modified: RwLock<Option<SystemTime>>,
+}
+
+impl TlsContext {
+    /// Loads the certificate chain, private key and optional client CA.
+    pub fn load(config: &TlsConfig) -> Result<Arc<Self>> {
+        let acceptor = build_acceptor(config)?;
+        info!(
+            "TLS enabled (certificate: {}, mutual TLS: {})",
+            config.cert_path,
+            config.client_ca_path.is_some()
+        );
+        Ok(Arc::new(Self {
+            config: config.clone(),
+            acceptor: RwLock::new(acceptor),
+            modified: RwLock::new(last_modified(config)),
+        }))
+    }
+
+    /// Returns the acceptor for a new connection.
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap().clone()
+    }
+
+    /// Re-reads the certificate files and swaps in the new configuration.
+    /// On failure the current configuration stays in place.
+    pub fn reload(&self) -> Result<()> {
+        let acceptor = build_acceptor(&self.config)?;
+        *self.acceptor.write().unwrap() = acceptor;
+        *self.modified.write().unwrap() = last_modified(&self.config);
+        info!("TLS certificates reloaded from {}", self.config.cert_path);
+        Ok(())
+    }
+
+    /// Reloads the certificates whenever one of the files changes on disk.
+    pub fn watch(self: &Arc<Self>) {
+        let context = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TLS_RELOAD_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let modified = last_modified(&context.config);
+                if modified == *context.modified.read().unwrap() {
+                    continue;
+                }
+                debug!("TLS certificate files changed, reloading");
+                if let Err(e) = context.reload() {
+                    error!("Failed to reload TLS certificates: {:#}", e);
+                    // Don't retry until the files change again.
+                    *context.modified.write().unwrap() = modified;
+                }
+            }
+        });
+    }
+}
+
+fn build_acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
+    let certs = load_certs(&config.cert_path)?;
+    let key = load_key(&config.key_path)?;
+
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(path)? {
+                roots.add(cert).context("Invalid client CA certificate")?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .context("Failed to build client certificate verifier")?;
+            ServerConfig::builder().with_client_cert_verifier(verifier)
+        }
+        None => ServerConfig::builder().with_no_client_auth(),
+    };
+
+    let server_config = builder
+        .with_single_cert(certs, key)
+        .context("Invalid TLS certificate or private key")?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open certificate file: {}", path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse certificates from: {}", path))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in: {}", path);
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("Failed to open private key file: {}", path))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse private key from: {}", path))?
+        .with_context(|| format!("No private key found in: {}", path))
+}
+
+fn last_modified(config: &TlsConfig) -> Option<SystemTime> {
+    [Some(&config.cert_path), Some(&config.key_path), config.client_ca_path.as_ref()]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| std::fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .max()
+}
@@ -0,0 +1,106 @@
+use log::{debug, warn};
+use tokio_tungstenite::tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::header::{AUTHORIZATION, HOST, ORIGIN, WWW_AUTHENTICATE};
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+
+use crate::auth::{authenticate, AuthError, Grant};
+use crate::config::model::Config;
+use crate::origin::OriginPolicy;
+use crate::routing::resolve_server;
+
+/// Handshake callback authenticating the upgrade request and recording
+/// which server the client asked for.
+pub struct Handshake<'a> {
+    config: &'a Config,
+    server_name: &'a mut Option<String>,
+}
+
+impl<'a> Handshake<'a> {
+    pub fn new(config: &'a Config, server_name: &'a mut Option<String>) -> Self {
+        Self { config, server_name }
+    }
+}
+
+impl Callback for Handshake<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        check_origin(request, self.config).map_err(Rejection::into_response)?;
+        let grant = check_credentials(request, self.config).map_err(Rejection::into_response)?;
+        let name = select_server(request, self.config).map_err(Rejection::into_response)?;
+        grant
+            .authorize(&name)
+            .map_err(|e| Rejection::from(e).into_response())?;
+        *self.server_name = Some(name);
+        Ok(response)
+    }
+}
+
+/// Reason for refusing a WebSocket upgrade, answered with a plain HTTP response.
+#[derive(Debug)]
+pub struct Rejection {
+    status: StatusCode,
+    message: String,
+}
+
+impl Rejection {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+
+    fn into_response(self) -> ErrorResponse {
+        let mut response = ErrorResponse::new(Some(self.message));
+        *response.status_mut() = self.status;
+        if self.status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+impl From<AuthError> for Rejection {
+    fn from(error: AuthError) -> Self {
+        let status = if error.is_challenge() {
+            StatusCode::UNAUTHORIZED
+        } else {
+            StatusCode::FORBIDDEN
+        };
+        Self::new(status, error.to_string())
+    }
+}
+
+/// Rejects upgrades from disallowed origins or for disallowed hosts with `403 Forbidden`.
+fn check_origin(request: &Request, config: &Config) -> Result<(), Rejection> {
+    let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok());
+    OriginPolicy::new(config, &config.host)
+        .check(header(ORIGIN), header(HOST))
+        .map_err(|e| Rejection::new(StatusCode::FORBIDDEN, e.to_string()))
+}
+
+/// Authenticates the handshake request against the configured credentials.
+fn check_credentials(request: &Request, config: &Config) -> Result<Grant, Rejection> {
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    authenticate(config.auth.as_ref(), authorization, request.uri().query()).map_err(|e| {
+        warn!("Handshake rejected for {}: {}", request.uri().path(), e);
+        Rejection::from(e)
+    })
+}
+
+/// Resolves which configured server a handshake request is asking for,
+/// rejecting unknown names with `404 Not Found`.
+fn select_server(request: &Request, config: &Config) -> Result<String, Rejection> {
+    let uri = request.uri();
+    match resolve_server(uri.path(), uri.query(), config) {
+        Ok(name) => {
+            debug!("Handshake for {} routed to server '{}'", uri.path(), name);
+            Ok(name)
+        }
+        Err(e) => {
+            warn!("Handshake rejected for {}: {}", uri.path(), e);
+            Err(Rejection::new(StatusCode::NOT_FOUND, e.to_string()))
+        }
+    }
+}
@@ -1,24 +1,38 @@
-use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use futures_util::{SinkExt, StreamExt};
-use log::{debug, error};
+use log::{debug, error, warn};
 use tokio::sync::mpsc;
-use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message};
 use futures_util::sink::Sink;
 
-use crate::state::SHUTDOWN;
+use crate::batch::BatchReplies;
+use crate::config::model::ToolPolicy;
+use crate::jsonrpc::{self, Frame};
+use crate::metrics::{metrics, Direction, Transport};
+use crate::state::SessionState;
 
+/// Forwards client messages to the session's process.
+///
+/// Calls to tools hidden by `tools` are answered with an error on `ws_tx`
+/// instead of being forwarded; errors for members of a batch are held in
+/// `batches` until the child has answered the rest of it.
 pub async fn handle_incoming_messages<S>(
     mut reader: S,
     process_tx: mpsc::Sender<String>,
+    ws_tx: mpsc::WeakSender<String>,
+    state: Arc<SessionState>,
+    tools: ToolPolicy,
+    batches: Arc<BatchReplies>,
 ) where
     S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
 {
     while let Some(msg) = reader.next().await {
-        if SHUTDOWN.load(Ordering::SeqCst) {
+        if state.is_shutdown() {
             break;
         }
 
-        match process_incoming_message(msg, &process_tx).await {
+        match process_incoming_message(msg, &process_tx, &ws_tx, &state, &tools, &batches).await {
             Ok(should_break) => {
                 if should_break {
                     break;
@@ -32,44 +46,117 @@ pub async fn handle_incoming_messages<S>(
     }
 }
 
+/// Forwards the process's output to the client.
+///
+/// A session closed from the runner's side (e.g. through the admin API)
+/// ends with a close frame rather than a dropped connection; at server
+/// shutdown the frame carries 1001 (going away).
 pub async fn handle_outgoing_messages<S>(
     mut writer: S,
     mut ws_rx: mpsc::Receiver<String>,
+    state: Arc<SessionState>,
+    batches: Arc<BatchReplies>,
 ) where
     S: Sink<Message> + Unpin,
     S::Error: std::fmt::Debug,
 {
-    while let Some(msg) = ws_rx.recv().await {
-        if SHUTDOWN.load(Ordering::SeqCst) {
-            break;
-        }
+    loop {
+        // Output already queued is still delivered once the session is closed.
+        let msg = tokio::select! {
+            biased;
+            msg = ws_rx.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = state.wait_shutdown() => break,
+        };
 
-        debug!("Sending process response: {}", msg);
-        if let Err(e) = writer.send(Message::Text(msg)).await {
-            error!("Error sending to WebSocket: {:?}", e);
-            break;
+        for msg in batches.complete(msg) {
+            debug!("Sending process response: {}", msg);
+            metrics().message(Transport::WebSocket, Direction::Out, msg.len());
+            state.count_sent();
+            if let Err(e) = writer.send(Message::Text(msg)).await {
+                error!("Error sending to WebSocket: {:?}", e);
+                return;
+            }
         }
     }
+
+    let frame = if state.is_going_away() {
+        Some((CloseCode::Away, "Server shutting down"))
+    } else if state.is_shutdown() {
+        Some((CloseCode::Normal, "Session closed"))
+    } else {
+        None
+    };
+    if let Some((code, reason)) = frame {
+        let frame = CloseFrame { code, reason: reason.into() };
+        writer.send(Message::Close(Some(frame))).await.ok();
+    }
 }
 
+/// Validates a frame from the client and forwards it to the process as a
+/// single line. Invalid messages and refused tool calls are answered on
+/// `ws_tx` without reaching the process, in one array for a batch.
 async fn process_incoming_message(
     msg: Result<Message, tokio_tungstenite::tungstenite::Error>,
     process_tx: &mpsc::Sender<String>,
+    ws_tx: &mpsc::WeakSender<String>,
+    state: &SessionState,
+    tools: &ToolPolicy,
+    batches: &BatchReplies,
 ) -> Result<bool, Box<dyn std::error::Error>> {
-    match msg {
-        Ok(msg) => {
-            if msg.is_close() {
-                return Ok(true);
-            }
-            if let Ok(text) = msg.into_text() {
-                debug!("Received from client: {}", text);
-                process_tx.send(text).await?;
-            }
-        }
+    let msg = match msg {
+        Ok(msg) => msg,
         Err(e) => {
             error!("Error receiving from WebSocket: {}", e);
             return Ok(true);
         }
+    };
+    if msg.is_close() {
+        return Ok(true);
+    }
+    if !msg.is_text() && !msg.is_binary() {
+        return Ok(false);
+    }
+    metrics().message(Transport::WebSocket, Direction::In, msg.len());
+    state.count_received();
+
+    let frame = match msg.into_text() {
+        Ok(text) => {
+            debug!("Received from client: {}", text);
+            jsonrpc::parse_frame(&text)
+        }
+        Err(e) => Frame::parse_error(e),
+    };
+    let mut replies = frame.errors;
+    if !replies.is_empty() {
+        warn!("Rejected {} invalid message(s) from client", replies.len());
+    }
+
+    let mut forward = frame.message.and_then(|message| {
+        state.record_client_message(&message);
+        let screened = tools.screen(message);
+        replies.extend(screened.rejected);
+        screened.forward
+    });
+    // Responses and notifications still reach the child while it drains.
+    if state.is_going_away() {
+        if let Some(message) = forward.take() {
+            let (rest, refused) = jsonrpc::refuse_requests(message, jsonrpc::SHUTTING_DOWN, "Server is shutting down");
+            replies.extend(refused);
+            forward = rest;
+        }
+    }
+
+    // Registered before forwarding so the child's responses can't overtake it.
+    let reply = batches.reply(forward.as_ref(), replies, frame.batch);
+    if let (Some(reply), Some(ws_tx)) = (reply, ws_tx.upgrade()) {
+        ws_tx.send(reply.to_string()).await?;
+    }
+    if let Some(message) = forward {
+        // Compact serialization keeps the message on a single line for the child's stdio framing.
+        process_tx.send(message.to_string()).await?;
     }
     Ok(false)
-}
\ No newline at end of file
+}
@@ -1,49 +1,93 @@
+mod handshake;
 mod message;
 
-use anyhow::Result;
-use log::info;
-use tokio::net::TcpStream;
+use anyhow::{Context, Result};
+use log::{debug, error, info};
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::mpsc;
-use tokio_tungstenite::accept_async;
-use std::sync::atomic::Ordering;
+use tokio_tungstenite::accept_hdr_async;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use std::sync::Arc;
 use futures_util::StreamExt;
 
-use crate::state::CONNECTED;
+use crate::config::model::Config;
+use crate::constants::MESSAGE_BUFFER_SIZE;
+use crate::metrics::{metrics, Transport};
+use crate::batch::BatchReplies;
+use crate::session::SessionRegistry;
+use self::handshake::Handshake;
 use self::message::{handle_incoming_messages, handle_outgoing_messages};
 
 /// Handle a new WebSocket connection
-pub async fn handle_connection(
-    stream: TcpStream,
-    process_tx: mpsc::Sender<String>,
-    ws_rx: mpsc::Receiver<String>,
-) -> Result<()> {
-    let addr = setup_connection(&stream)?;
-    let ws_stream = accept_async(stream).await?;
-    
-    info!("WebSocket connection established: {}", addr);
+///
+/// The target server is chosen from the handshake request, after which a
+/// dedicated session and child process are started for the client.
+/// `stream` is either a plain TCP stream or one already wrapped in TLS.
+pub async fn handle_connection<S>(
+    stream: S,
+    addr: SocketAddr,
+    config: Arc<Config>,
+    registry: Arc<SessionRegistry>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+
+    let mut server_name = None;
+    let handshake = accept_hdr_async(stream, Handshake::new(&config, &mut server_name)).await;
+    metrics().connection(Transport::WebSocket, handshake.is_ok());
+    let mut ws_stream = handshake?;
+    let server_name = server_name.context("Handshake completed without selecting a server")?;
+
+    info!("WebSocket connection established: {} (server: {})", addr, server_name);
+
+    let (ws_tx, ws_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+    debug!(
+        "Created message channels with buffer size: {}",
+        MESSAGE_BUFFER_SIZE
+    );
+
+    let reply_tx = ws_tx.downgrade();
+    let session = match registry.open(addr, &server_name, &config, ws_tx).await {
+        Ok(session) => {
+            info!(
+                "Successfully started child process for session {}: {}",
+                session.id, server_name
+            );
+            session
+        }
+        Err(e) => {
+            error!("Failed to start process: {}. Connection will be closed", e);
+            let frame = CloseFrame {
+                code: CloseCode::Error,
+                reason: "Failed to start MCP server".into(),
+            };
+            ws_stream.close(Some(frame)).await.ok();
+            return Err(e);
+        }
+    };
+
     let (ws_writer, ws_reader) = ws_stream.split();
+    let batches = Arc::new(BatchReplies::default());
 
-    let ws_to_process = handle_incoming_messages(ws_reader, process_tx);
-    let process_to_ws = handle_outgoing_messages(ws_writer, ws_rx);
+    let ws_to_process = handle_incoming_messages(
+        ws_reader,
+        session.process_tx,
+        reply_tx,
+        Arc::clone(&session.state),
+        session.tools,
+        Arc::clone(&batches),
+    );
+    let process_to_ws = handle_outgoing_messages(ws_writer, ws_rx, session.state, batches);
 
     tokio::select! {
         _ = ws_to_process => info!("WebSocket -> Process handling completed"),
         _ = process_to_ws => info!("Process -> WebSocket handling completed"),
     }
     
-    cleanup_connection(addr);
+    registry.close(session.id).await;
+    info!("Client disconnected: {}", addr);
     Ok(())
 }
-
-/// Set up initial connection state
-fn setup_connection(stream: &TcpStream) -> Result<std::net::SocketAddr> {
-    let addr = stream.peer_addr()?;
-    CONNECTED.store(true, Ordering::SeqCst);
-    Ok(addr)
-}
-
-/// Clean up connection state
-fn cleanup_connection(addr: std::net::SocketAddr) {
-    CONNECTED.store(false, Ordering::SeqCst);
-    info!("Client disconnected: {}", addr);
-}
\ No newline at end of file